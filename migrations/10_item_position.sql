@@ -0,0 +1,2 @@
+-- explicit order of the items within a day, items without one come after by timestamp
+ALTER TABLE items ADD COLUMN position integer;
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS budget_schedule
+(
+    effective_from text NOT NULL, -- stored as 'YYYY-MM-DD'
+    weekday integer NOT NULL, -- 0 = monday, 6 = sunday
+    budget real NOT NULL,
+    PRIMARY KEY (effective_from, weekday)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS conf_history
+(
+    key text NOT NULL,
+    value text NOT NULL,
+    effective_from text NOT NULL, -- stored as 'YYYY-MM-DD'
+    PRIMARY KEY (key, effective_from)
+);
+
+-- days logged before conf was versioned keep using the values set at that point
+INSERT INTO conf_history (key, value, effective_from)
+SELECT key, value, '0000-01-01' FROM conf WHERE key IN ('budget', 'metabolism');
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS metric
+(
+    name text primary key,
+    unit text NOT NULL
+);
+
+INSERT INTO metric (name, unit) VALUES ('weight', 'kg');
+
+CREATE TABLE IF NOT EXISTS measurement
+(
+    metric text NOT NULL REFERENCES metric (name),
+    date text NOT NULL, -- stored as 'YYYY-MM-DD'
+    value real NOT NULL,
+    PRIMARY KEY (metric, date)
+);
+
+INSERT INTO measurement (metric, date, value) SELECT 'weight', date, weight FROM weight;
+
+DROP TABLE weight;
+
+-- weight is now a measurement like the others, the view keeps the read queries working
+CREATE VIEW weight AS SELECT date, value AS weight FROM measurement WHERE metric = 'weight';
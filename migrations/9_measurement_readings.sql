@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS measurement_reading
+(
+    id integer primary key autoincrement,
+    metric text NOT NULL REFERENCES metric (name),
+    date text NOT NULL, -- stored as 'YYYY-MM-DD'
+    timestamp integer NOT NULL,
+    value real NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_measurement_reading_metric_date on measurement_reading (metric, date);
+
+INSERT INTO measurement_reading (metric, date, timestamp, value)
+SELECT metric, date, CAST(strftime('%s', date) AS integer), value FROM measurement;
+
+DROP VIEW weight;
+DROP TABLE measurement;
+
+-- one value per metric and day, picked from the readings according to the daily_measurement conf
+CREATE VIEW measurement AS
+SELECT metric,
+       date,
+       CASE (SELECT value FROM conf WHERE key = 'daily_measurement')
+           WHEN 'min' THEN min(value)
+           WHEN 'average' THEN avg(value)
+           WHEN 'first' THEN (SELECT value FROM measurement_reading f
+                              WHERE f.metric = r.metric AND f.date = r.date
+                              ORDER BY timestamp, id LIMIT 1)
+           ELSE (SELECT value FROM measurement_reading l
+                 WHERE l.metric = r.metric AND l.date = r.date
+                 ORDER BY timestamp DESC, id DESC LIMIT 1)
+       END AS value
+FROM measurement_reading r
+GROUP BY metric, date;
+
+CREATE VIEW weight AS SELECT date, value AS weight FROM measurement WHERE metric = 'weight';
@@ -0,0 +1,172 @@
+use crate::conf::{Conf, Sex};
+use crate::db::Database;
+use crate::units::Units;
+use crate::{latest_weight, parse_date};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct BmrFormulas {
+    mifflin_st_jeor: Option<f64>,
+    harris_benedict: Option<f64>,
+    /// Only available when body_fat is set
+    katch_mcardle: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct BmrReport {
+    /// Latest weight used for the computation and the date it was logged
+    weight: Option<f64>,
+    weight_date: Option<String>,
+    age: Option<u32>,
+    bmr: BmrFormulas,
+    activity_factor: f64,
+    /// BMR times the activity factor, using Katch-McArdle when body fat is known
+    /// and Mifflin-St Jeor otherwise. Can be used as the metabolism conf.
+    suggested_metabolism: Option<f64>,
+    /// Profile conf keys that need to be set to compute everything
+    missing: Vec<&'static str>,
+}
+
+pub fn age_at(birth: NaiveDate, date: NaiveDate) -> Option<u32> {
+    let mut age = date.year() - birth.year();
+    if (date.month(), date.day()) < (birth.month(), birth.day()) {
+        age -= 1;
+    }
+    u32::try_from(age).ok()
+}
+
+/// weight in kg, height in cm, age in years
+pub fn mifflin_st_jeor(sex: Sex, weight: f64, height: f64, age: u32) -> f64 {
+    let base = 10.0 * weight + 6.25 * height - 5.0 * age as f64;
+    match sex {
+        Sex::Male => base + 5.0,
+        Sex::Female => base - 161.0,
+    }
+}
+
+/// Revised by Roza and Shizgal (1984)
+pub fn harris_benedict(sex: Sex, weight: f64, height: f64, age: u32) -> f64 {
+    let age = age as f64;
+    match sex {
+        Sex::Male => 88.362 + 13.397 * weight + 4.799 * height - 5.677 * age,
+        Sex::Female => 447.593 + 9.247 * weight + 3.098 * height - 4.330 * age,
+    }
+}
+
+/// Only depends on lean body mass
+pub fn katch_mcardle(weight: f64, body_fat: f64) -> f64 {
+    370.0 + 21.6 * weight * (1.0 - body_fat / 100.0)
+}
+
+impl BmrReport {
+    fn in_units(self, units: Units) -> Self {
+        let energy = |x: Option<f64>| x.map(|x| units.energy(x));
+        Self {
+            weight: self.weight.map(|x| units.weight(x)),
+            bmr: BmrFormulas {
+                mifflin_st_jeor: energy(self.bmr.mifflin_st_jeor),
+                harris_benedict: energy(self.bmr.harris_benedict),
+                katch_mcardle: energy(self.bmr.katch_mcardle),
+            },
+            suggested_metabolism: energy(self.suggested_metabolism).map(f64::round),
+            ..self
+        }
+    }
+}
+
+pub fn compute(conf: &Conf, weight: Option<(String, f64)>, today: NaiveDate) -> BmrReport {
+    let age = conf
+        .birth_date
+        .as_deref()
+        .and_then(parse_date)
+        .and_then(|birth| age_at(birth, today));
+    let (weight_date, weight) = match weight {
+        Some((date, w)) => (Some(date), Some(w)),
+        None => (None, None),
+    };
+
+    let mut missing = vec![];
+    for (key, is_missing) in [
+        ("sex", conf.sex.is_none()),
+        ("birth_date", age.is_none()),
+        ("height", conf.height.is_none()),
+        ("weight", weight.is_none()),
+    ] {
+        if is_missing {
+            missing.push(key);
+        }
+    }
+
+    let mut bmr = BmrFormulas::default();
+    if let (Some(sex), Some(w), Some(h), Some(a)) = (conf.sex, weight, conf.height, age) {
+        bmr.mifflin_st_jeor = Some(mifflin_st_jeor(sex, w, h, a));
+        bmr.harris_benedict = Some(harris_benedict(sex, w, h, a));
+    }
+    bmr.katch_mcardle = weight
+        .zip(conf.body_fat)
+        .map(|(w, bf)| katch_mcardle(w, bf));
+
+    let activity_factor = conf.activity_level.factor();
+    BmrReport {
+        weight,
+        weight_date,
+        age,
+        suggested_metabolism: bmr
+            .katch_mcardle
+            .or(bmr.mifflin_st_jeor)
+            .map(|x| (x * activity_factor).round()),
+        bmr,
+        activity_factor,
+        missing,
+    }
+}
+
+pub async fn bmr(Extension(db): Extension<Database>) -> impl IntoResponse {
+    tracing::info!("computing bmr");
+    let conn = db.connection().expect("could not get connection");
+    let conf = Conf::from_db(&conn);
+    let report = compute(&conf, latest_weight(&conn), conf.today()).in_units(Units::of(&conf));
+    (StatusCode::OK, Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::ActivityLevel;
+
+    #[test]
+    fn test_compute() {
+        let mut conf = Conf::default();
+        let today = NaiveDate::from_ymd(2022, 6, 1);
+        let weight = Some(("2022-05-30".to_string(), 80.0));
+
+        let report = compute(&conf, weight.clone(), today);
+        assert_eq!(report.missing, vec!["sex", "birth_date", "height"]);
+        assert_eq!(report.suggested_metabolism, None);
+
+        conf.set("sex", "male").unwrap();
+        conf.set("birth_date", "1992-06-02").unwrap();
+        conf.set("height", "180").unwrap();
+        conf.set("activity_level", "moderate").unwrap();
+        let report = compute(&conf, weight.clone(), today);
+        assert_eq!(report.age, Some(29));
+        assert_eq!(report.bmr.mifflin_st_jeor, Some(1785.0));
+        assert_eq!(
+            report.suggested_metabolism,
+            Some((1785.0 * 1.55_f64).round())
+        );
+
+        conf.set("body_fat", "20").unwrap();
+        let report = compute(&conf, weight, today);
+        assert_eq!(report.bmr.katch_mcardle, Some(370.0 + 21.6 * 64.0));
+        assert_eq!(
+            report.suggested_metabolism,
+            Some(((370.0 + 21.6 * 64.0) * 1.55_f64).round())
+        );
+        assert_eq!(ActivityLevel::Sedentary.factor(), 1.2);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::conf::{Conf, DAY_METABOLISM_SUBQUERY};
+use crate::db::Database;
+use crate::units::Units;
+use crate::{check_date, parse_date, to_year_month_day};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{Datelike, NaiveDate};
+use r2d2_sqlite::rusqlite::{params, Connection, Error};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct ScheduleEntry {
+    /// YYYY-MM-DD, the entry applies from this date until a newer entry for the same weekday
+    /// or a newer conf budget
+    effective_from: String,
+    /// 0 = monday, 6 = sunday
+    weekday: u32,
+    budget: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetSchedule {
+    effective_from: String,
+    /// 0 = monday, 6 = sunday. Applies to every weekday when missing.
+    weekday: Option<u32>,
+    budget: f64,
+}
+
+/// Budget in effect on the given date: the newest of the schedule entries for its
+/// weekday and the conf_history budgets starting on or before it, the schedule winning
+/// when both start on the same day. `default` if there is none.
+pub fn budget_for(conn: &Connection, date: &NaiveDate, default: f64) -> f64 {
+    let mut qry = conn
+        .prepare_cached(
+            "SELECT budget FROM (
+                SELECT effective_from, 1 as scheduled, budget FROM budget_schedule
+                WHERE weekday = ?1 AND effective_from <= ?2
+                UNION ALL
+                SELECT effective_from, 0, cast(value as real) FROM conf_history
+                WHERE key = 'budget' AND effective_from <= ?2
+            ) ORDER BY effective_from DESC, scheduled DESC LIMIT 1",
+        )
+        .expect("could not prepare qry");
+    match qry.query_row(
+        params![
+            date.weekday().num_days_from_monday(),
+            to_year_month_day(date)
+        ],
+        |row| row.get(0),
+    ) {
+        Ok(x) => x,
+        Err(Error::QueryReturnedNoRows) => default,
+        Err(e) => panic!("could not get budget for the day: {}", e),
+    }
+}
+
+/// Scalar subquery of the budget in effect on `days.date`, resolved like budget_for.
+/// NULL when there is neither a schedule entry nor a conf_history budget.
+pub const DAY_BUDGET_SUBQUERY: &str = "(SELECT budget FROM (
+        SELECT effective_from, 1 as scheduled, budget FROM budget_schedule
+        WHERE weekday = (cast(strftime('%w', days.date) as integer) + 6) % 7
+        AND effective_from <= days.date
+        UNION ALL
+        SELECT effective_from, 0, cast(value as real) FROM conf_history
+        WHERE key = 'budget' AND effective_from <= days.date
+    ) ORDER BY effective_from DESC, scheduled DESC LIMIT 1)";
+
+/// Intake of a day with items along with what was in effect that day
+pub struct LoggedDay {
+    /// YYYY-MM-DD
+    pub date: String,
+    pub total: f64,
+    pub n_items: u32,
+    pub budget: f64,
+    pub metabolism: f64,
+}
+
+/// Days with items between the two YYYY-MM-DD dates included, ordered by date,
+/// in a single query
+pub fn logged_days(conn: &Connection, from: &str, to: &str) -> Vec<LoggedDay> {
+    let conf = Conf::from_db(conn);
+    let mut qry = conn
+        .prepare_cached(&format!(
+            "SELECT days.date, days.total, days.n_items, coalesce({}, ?3), coalesce({}, ?4)
+            FROM (
+                SELECT date, sum(calories * multiplier) as total, count(*) as n_items
+                FROM items WHERE date BETWEEN ?1 AND ?2 GROUP BY date
+            ) days ORDER BY days.date",
+            DAY_BUDGET_SUBQUERY, DAY_METABOLISM_SUBQUERY
+        ))
+        .expect("could not prepare qry");
+    let rows = qry
+        .query_map(params![from, to, conf.budget, conf.metabolism], |row| {
+            Ok(LoggedDay {
+                date: row.get(0)?,
+                total: row.get(1)?,
+                n_items: row.get(2)?,
+                budget: row.get(3)?,
+                metabolism: row.get(4)?,
+            })
+        })
+        .expect("could not query");
+    rows.map(|row| row.expect("could not get row")).collect()
+}
+
+/// Budget in effect on the given YYYY-MM-DD date, falling back to the conf budget of that date
+pub fn daily_budget(conn: &Connection, date: &str) -> f64 {
+    let default = Conf::at(conn, date).budget;
+    parse_date(date)
+        .map(|d| budget_for(conn, &d, default))
+        .unwrap_or(default)
+}
+
+pub async fn get_schedule(Extension(db): Extension<Database>) -> impl IntoResponse {
+    tracing::info!("getting budget schedule");
+    let conn = db.connection().expect("could not get connection");
+    let units = Units::from_db(&conn);
+    let mut qry = conn
+        .prepare_cached(
+            "SELECT effective_from, weekday, budget FROM budget_schedule
+            ORDER BY effective_from, weekday",
+        )
+        .expect("could not prepare qry");
+    let mut rows = qry.query([]).expect("could not run qry");
+
+    let mut entries = vec![];
+    while let Ok(Some(row)) = rows.next() {
+        entries.push(ScheduleEntry {
+            effective_from: row.get_unwrap("effective_from"),
+            weekday: row.get_unwrap("weekday"),
+            budget: units.energy(row.get_unwrap("budget")),
+        });
+    }
+    (StatusCode::OK, Json(entries))
+}
+
+pub async fn set_schedule(
+    Json(entry): Json<SetSchedule>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("setting budget schedule {:?}", entry);
+    if !check_date(&entry.effective_from)
+        || parse_date(&entry.effective_from).is_none()
+        || entry.weekday.map(|w| w > 6).unwrap_or(false)
+        || !entry.budget.is_finite()
+        || entry.budget <= 0.0
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+    let weekdays = match entry.weekday {
+        Some(w) => w..=w,
+        None => 0..=6,
+    };
+    let mut conn = db.connection().expect("could not get connection");
+    let budget = Units::from_db(&conn).energy_in(entry.budget);
+    let tx = conn.transaction().expect("could not start transaction");
+    for weekday in weekdays {
+        tx.execute(
+            "INSERT INTO budget_schedule (effective_from, weekday, budget) VALUES (?1, ?2, ?3)
+            ON CONFLICT (effective_from, weekday) DO UPDATE SET budget = ?3;",
+            params![entry.effective_from, weekday, budget],
+        )
+        .expect("could not insert budget schedule");
+    }
+    tx.commit().expect("could not commit budget schedule");
+    StatusCode::CREATED
+}
+
+pub async fn remove_schedule(
+    Path(effective_from): Path<String>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("removing budget schedule from {}", effective_from);
+    let conn = db.connection().expect("could not get connection");
+    let n_removed = conn
+        .execute(
+            "DELETE FROM budget_schedule WHERE effective_from = ?1;",
+            [&effective_from],
+        )
+        .expect("could not remove budget schedule");
+    if n_removed == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+    StatusCode::OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrate::test_db;
+
+    #[test]
+    fn test_budget_precedence() {
+        let conn = test_db();
+        // sundays from 2022-01-01, every day from 2022-03-01
+        conn.execute(
+            "INSERT INTO budget_schedule VALUES ('2022-01-01', 6, 2500);",
+            [],
+        )
+        .unwrap();
+        Conf::store(&conn, "budget", "1800", Some("2022-03-01"), "2022-01-01").unwrap();
+
+        assert_eq!(daily_budget(&conn, "2022-02-06"), 2500.0);
+        assert_eq!(daily_budget(&conn, "2022-02-07"), 1500.0);
+        assert_eq!(daily_budget(&conn, "2022-03-06"), 1800.0);
+        assert_eq!(daily_budget(&conn, "2022-03-07"), 1800.0);
+
+        // a schedule entry starting the same day as a conf budget wins
+        conn.execute(
+            "INSERT INTO budget_schedule VALUES ('2022-03-01', 6, 2400);",
+            [],
+        )
+        .unwrap();
+        assert_eq!(daily_budget(&conn, "2022-03-06"), 2400.0);
+        assert_eq!(daily_budget(&conn, "2022-03-07"), 1800.0);
+
+        for date in ["2022-02-06", "2022-02-07", "2022-03-06", "2022-03-07"] {
+            conn.execute(
+                "INSERT INTO items (name, calories, multiplier, date, timestamp) VALUES ('x', 100, 2, ?1, 0);",
+                [date],
+            )
+            .unwrap();
+        }
+        let days = logged_days(&conn, "2022-01-01", "2022-12-31");
+        assert_eq!(days.len(), 4);
+        for day in days {
+            assert_eq!((day.total, day.n_items), (200.0, 1));
+            assert_eq!(day.budget, daily_budget(&conn, &day.date));
+            assert_eq!(day.metabolism, Conf::at(&conn, &day.date).metabolism);
+        }
+    }
+}
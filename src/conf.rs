@@ -0,0 +1,499 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use r2d2_sqlite::rusqlite::{params, Connection};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeightUnit {
+    #[serde(rename = "kg")]
+    Kg,
+    #[serde(rename = "lb")]
+    Lb,
+}
+
+impl WeightUnit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WeightUnit::Kg => "kg",
+            WeightUnit::Lb => "lb",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnergyUnit {
+    #[serde(rename = "kcal")]
+    Kcal,
+    #[serde(rename = "kJ")]
+    Kj,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityLevel {
+    Sedentary,
+    Light,
+    Moderate,
+    Active,
+    VeryActive,
+}
+
+impl ActivityLevel {
+    /// Multiplier from BMR to daily expenditure
+    pub fn factor(self) -> f64 {
+        match self {
+            ActivityLevel::Sedentary => 1.2,
+            ActivityLevel::Light => 1.375,
+            ActivityLevel::Moderate => 1.55,
+            ActivityLevel::Active => 1.725,
+            ActivityLevel::VeryActive => 1.9,
+        }
+    }
+}
+
+/// Which reading gives the value of a day with several measurements
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DailyMeasurement {
+    First,
+    Last,
+    Min,
+    Average,
+}
+
+/// Keys that change how past days are computed, their values are versioned in conf_history
+pub const VERSIONED_KEYS: &[&str] = &["budget", "metabolism"];
+
+/// Scalar subquery of the metabolism in effect on `days.date`, resolved like Conf::at.
+/// NULL when conf_history has none.
+pub const DAY_METABOLISM_SUBQUERY: &str = "(SELECT cast(value as real) FROM conf_history
+    WHERE key = 'metabolism' AND effective_from <= days.date
+    ORDER BY effective_from DESC LIMIT 1)";
+
+#[derive(Serialize)]
+pub struct ConfHistoryEntry {
+    pub key: String,
+    pub value: String,
+    /// YYYY-MM-DD
+    pub effective_from: String,
+}
+
+/// Typed view of the key/value rows of the conf table.
+/// Missing or invalid rows fall back to the defaults.
+#[derive(Serialize, Clone, Debug)]
+pub struct Conf {
+    pub budget: f64,
+    pub metabolism: f64,
+    pub weight_unit: WeightUnit,
+    pub energy_unit: EnergyUnit,
+    /// IANA name such as "Europe/Paris"
+    pub timezone: String,
+    /// Local hour at which a new day starts, items logged before it count for the previous day
+    pub day_rollover_hour: u32,
+    pub goal_weight: Option<f64>,
+    /// YYYY-MM-DD
+    pub goal_date: Option<String>,
+    pub sex: Option<Sex>,
+    /// YYYY-MM-DD
+    pub birth_date: Option<String>,
+    /// In cm
+    pub height: Option<f64>,
+    pub activity_level: ActivityLevel,
+    /// In percent
+    pub body_fat: Option<f64>,
+    /// Between 0 and 1, how fast the weight trend follows new weigh-ins
+    pub weight_smoothing: f64,
+    /// Read directly by the measurement view
+    pub daily_measurement: DailyMeasurement,
+}
+
+impl Default for Conf {
+    fn default() -> Self {
+        Self {
+            budget: 1500.0,
+            metabolism: 2200.0,
+            weight_unit: WeightUnit::Kg,
+            energy_unit: EnergyUnit::Kcal,
+            timezone: "UTC".to_string(),
+            day_rollover_hour: 0,
+            goal_weight: None,
+            goal_date: None,
+            sex: None,
+            birth_date: None,
+            height: None,
+            activity_level: ActivityLevel::Sedentary,
+            body_fat: None,
+            weight_smoothing: 0.1,
+            daily_measurement: DailyMeasurement::Last,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfError {
+    UnknownKey(String),
+    Invalid { key: String, reason: String },
+}
+
+impl Display for ConfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfError::UnknownKey(key) => write!(f, "unknown conf key: {}", key),
+            ConfError::Invalid { key, reason } => {
+                write!(f, "invalid value for {}: {}", key, reason)
+            }
+        }
+    }
+}
+
+impl IntoResponse for ConfError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+    }
+}
+
+fn invalid(key: &str, reason: impl Into<String>) -> ConfError {
+    ConfError::Invalid {
+        key: key.to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn parse_positive(key: &str, value: &str) -> Result<f64, ConfError> {
+    let v: f64 = value
+        .parse()
+        .map_err(|_| invalid(key, format!("{:?} is not a number", value)))?;
+    if !v.is_finite() || v <= 0.0 {
+        return Err(invalid(key, "must be a positive number"));
+    }
+    Ok(v)
+}
+
+fn parse_day(key: &str, value: &str) -> Result<String, ConfError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|_| value.to_string())
+        .map_err(|_| invalid(key, "expected YYYY-MM-DD"))
+}
+
+/// An empty value clears an optional setting
+fn parse_optional<T>(
+    value: &str,
+    parse: impl FnOnce(&str) -> Result<T, ConfError>,
+) -> Result<Option<T>, ConfError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    parse(value).map(Some)
+}
+
+impl Conf {
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// Day a unix timestamp counts for, in the timezone and with the day rollover hour
+    pub fn day_of(&self, timestamp: i64) -> NaiveDate {
+        let local = self.tz().timestamp(timestamp, 0).naive_local();
+        (local - Duration::hours(self.day_rollover_hour as i64)).date()
+    }
+
+    /// Unix timestamp of the local time on the day, inverse of day_of:
+    /// times before the rollover hour are on the next calendar date
+    pub fn timestamp_at(&self, day: NaiveDate, time: NaiveTime) -> Option<i64> {
+        let date = if time.hour() < self.day_rollover_hour {
+            day.succ_opt()?
+        } else {
+            day
+        };
+        self.tz()
+            .from_local_datetime(&date.and_time(time))
+            .earliest()
+            .map(|t| t.timestamp())
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.day_of(Utc::now().timestamp())
+    }
+
+    pub fn local_time(&self, timestamp: i64) -> NaiveTime {
+        self.tz().timestamp(timestamp, 0).time()
+    }
+
+    /// Local HH:MM of a unix timestamp
+    pub fn time_of_day(&self, timestamp: i64) -> String {
+        self.local_time(timestamp).format("%H:%M").to_string()
+    }
+
+    /// Current conf: versioned keys take their conf_history value effective today
+    pub fn from_db(conn: &Connection) -> Self {
+        let conf = Self::from_conf_table(conn);
+        let today = conf.today().format("%Y-%m-%d").to_string();
+        conf.with_history(conn, &today)
+    }
+
+    /// Conf as it was on the given YYYY-MM-DD date: versioned keys take the newest
+    /// conf_history value effective on or before it, falling back to the conf table.
+    pub fn at(conn: &Connection, date: &str) -> Self {
+        Self::from_conf_table(conn).with_history(conn, date)
+    }
+
+    fn from_conf_table(conn: &Connection) -> Self {
+        let mut qry = conn
+            .prepare_cached("SELECT key, value FROM conf;")
+            .expect("could not prepare qry");
+        let mut rows = qry.query([]).expect("could not do query");
+        let mut conf = Self::default();
+
+        while let Ok(Some(row)) = rows.next() {
+            let key: String = row.get_unwrap("key");
+            let value: String = row.get_unwrap("value");
+            if let Err(e) = conf.set(&key, &value) {
+                tracing::warn!("ignoring conf row: {}", e);
+            }
+        }
+        conf
+    }
+
+    fn with_history(mut self, conn: &Connection, date: &str) -> Self {
+        let mut qry = conn
+            .prepare_cached(
+                "SELECT key, value FROM conf_history h WHERE effective_from = (
+                    SELECT max(effective_from) FROM conf_history
+                    WHERE key = h.key AND effective_from <= ?1
+                );",
+            )
+            .expect("could not prepare qry");
+        let mut rows = qry.query([date]).expect("could not do query");
+
+        while let Ok(Some(row)) = rows.next() {
+            let key: String = row.get_unwrap("key");
+            let value: String = row.get_unwrap("value");
+            if let Err(e) = self.set(&key, &value) {
+                tracing::warn!("ignoring conf_history row: {}", e);
+            }
+        }
+        self
+    }
+
+    pub fn history(conn: &Connection) -> Vec<ConfHistoryEntry> {
+        let mut qry = conn
+            .prepare_cached(
+                "SELECT key, value, effective_from FROM conf_history ORDER BY key, effective_from;",
+            )
+            .expect("could not prepare qry");
+        let mut rows = qry.query([]).expect("could not do query");
+        let mut v = vec![];
+
+        while let Ok(Some(row)) = rows.next() {
+            v.push(ConfHistoryEntry {
+                key: row.get_unwrap("key"),
+                value: row.get_unwrap("value"),
+                effective_from: row.get_unwrap("effective_from"),
+            });
+        }
+        v
+    }
+
+    /// Validates and stores a single key/value.
+    /// Versioned keys are only recorded in conf_history, from `effective_from` or
+    /// `today` when missing (both dates are YYYY-MM-DD).
+    pub fn store(
+        conn: &Connection,
+        key: &str,
+        value: &str,
+        effective_from: Option<&str>,
+        today: &str,
+    ) -> Result<(), ConfError> {
+        let value = value.trim();
+        Self::from_db(conn).set(key, value)?;
+
+        if !VERSIONED_KEYS.contains(&key) {
+            if effective_from.is_some() {
+                return Err(invalid(key, "this setting does not have an effective date"));
+            }
+            conn.execute(
+                "INSERT INTO conf (key, value) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET value = ?2;",
+                params![key, value],
+            )
+            .expect("could not insert conf");
+            return Ok(());
+        }
+
+        conn.execute(
+            "INSERT INTO conf_history (key, value, effective_from) VALUES (?1, ?2, ?3)
+            ON CONFLICT (key, effective_from) DO UPDATE SET value = ?2;",
+            params![key, value, effective_from.unwrap_or(today)],
+        )
+        .expect("could not insert conf_history");
+        Ok(())
+    }
+
+    /// Validates and applies a single key/value, as stored in the conf table
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfError> {
+        let value = value.trim();
+        match key {
+            "budget" => self.budget = parse_positive(key, value)?,
+            "metabolism" => self.metabolism = parse_positive(key, value)?,
+            "weight_unit" => {
+                self.weight_unit = match value {
+                    "kg" => WeightUnit::Kg,
+                    "lb" => WeightUnit::Lb,
+                    _ => return Err(invalid(key, "expected kg or lb")),
+                }
+            }
+            "energy_unit" => {
+                self.energy_unit = match value {
+                    "kcal" => EnergyUnit::Kcal,
+                    "kJ" => EnergyUnit::Kj,
+                    _ => return Err(invalid(key, "expected kcal or kJ")),
+                }
+            }
+            "timezone" => {
+                if value.parse::<Tz>().is_err() {
+                    return Err(invalid(key, format!("{:?} is not a known timezone", value)));
+                }
+                self.timezone = value.to_string();
+            }
+            "day_rollover_hour" => {
+                self.day_rollover_hour = match value.trim().parse() {
+                    Ok(h) if h < 24 => h,
+                    _ => return Err(invalid(key, "expected an hour between 0 and 23")),
+                }
+            }
+            "goal_weight" => self.goal_weight = parse_optional(value, |v| parse_positive(key, v))?,
+            "goal_date" => self.goal_date = parse_optional(value, |v| parse_day(key, v))?,
+            "sex" => {
+                self.sex = parse_optional(value, |v| match v {
+                    "male" => Ok(Sex::Male),
+                    "female" => Ok(Sex::Female),
+                    _ => Err(invalid(key, "expected male or female")),
+                })?
+            }
+            "birth_date" => self.birth_date = parse_optional(value, |v| parse_day(key, v))?,
+            "height" => self.height = parse_optional(value, |v| parse_positive(key, v))?,
+            "activity_level" => {
+                self.activity_level = match value {
+                    "sedentary" => ActivityLevel::Sedentary,
+                    "light" => ActivityLevel::Light,
+                    "moderate" => ActivityLevel::Moderate,
+                    "active" => ActivityLevel::Active,
+                    "very_active" => ActivityLevel::VeryActive,
+                    _ => {
+                        return Err(invalid(
+                            key,
+                            "expected sedentary, light, moderate, active or very_active",
+                        ))
+                    }
+                }
+            }
+            "body_fat" => {
+                self.body_fat = parse_optional(value, |v| {
+                    let v = parse_positive(key, v)?;
+                    if v >= 100.0 {
+                        return Err(invalid(key, "must be a percentage below 100"));
+                    }
+                    Ok(v)
+                })?
+            }
+            "weight_smoothing" => {
+                let v = parse_positive(key, value)?;
+                if v > 1.0 {
+                    return Err(invalid(key, "must be between 0 and 1"));
+                }
+                self.weight_smoothing = v;
+            }
+            "daily_measurement" => {
+                self.daily_measurement = match value {
+                    "first" => DailyMeasurement::First,
+                    "last" => DailyMeasurement::Last,
+                    "min" => DailyMeasurement::Min,
+                    "average" => DailyMeasurement::Average,
+                    _ => return Err(invalid(key, "expected first, last, min or average")),
+                }
+            }
+            _ => return Err(ConfError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut conf = Conf::default();
+        conf.set("budget", " 1800 ").unwrap();
+        assert_eq!(conf.budget, 1800.0);
+        conf.set("weight_unit", "lb").unwrap();
+        assert_eq!(conf.weight_unit, WeightUnit::Lb);
+        conf.set("timezone", "Europe/Paris").unwrap();
+        conf.set("day_rollover_hour", "4").unwrap();
+        // 2022-06-01 01:30 in Paris
+        assert_eq!(conf.day_of(1654039800), NaiveDate::from_ymd(2022, 5, 31));
+        assert_eq!(conf.time_of_day(1654039800), "01:30");
+        let time = NaiveTime::from_hms(1, 30, 0);
+        assert_eq!(
+            conf.timestamp_at(NaiveDate::from_ymd(2022, 5, 31), time),
+            Some(1654039800)
+        );
+        conf.set("day_rollover_hour", "0").unwrap();
+        assert_eq!(conf.day_of(1654039800), NaiveDate::from_ymd(2022, 6, 1));
+        assert!(conf.set("day_rollover_hour", "24").is_err());
+        conf.set("goal_weight", "70").unwrap();
+        conf.set("goal_weight", "").unwrap();
+        assert_eq!(conf.goal_weight, None);
+
+        assert!(matches!(
+            conf.set("budget", "abc"),
+            Err(ConfError::Invalid { .. })
+        ));
+        assert!(matches!(
+            conf.set("budget", "-5"),
+            Err(ConfError::Invalid { .. })
+        ));
+        assert!(matches!(
+            conf.set("timezone", "Mars/Olympus"),
+            Err(ConfError::Invalid { .. })
+        ));
+        assert!(matches!(
+            conf.set("goal_date", "2022-13-01"),
+            Err(ConfError::Invalid { .. })
+        ));
+        assert!(matches!(
+            conf.set("colour", "red"),
+            Err(ConfError::UnknownKey(_))
+        ));
+        assert_eq!(conf.budget, 1800.0);
+    }
+
+    #[test]
+    fn test_versioned_store() {
+        let conn = crate::migrate::test_db();
+
+        Conf::store(&conn, "budget", "1800", None, "2022-05-10").unwrap();
+        Conf::store(&conn, "budget", "2000", Some("2022-06-01"), "2022-05-10").unwrap();
+        Conf::store(&conn, "weight_unit", "lb", None, "2022-05-10").unwrap();
+        assert!(Conf::store(&conn, "weight_unit", "kg", Some("2022-06-01"), "2022-05-10").is_err());
+
+        assert_eq!(Conf::at(&conn, "2021-01-01").budget, 1500.0);
+        assert_eq!(Conf::at(&conn, "2022-05-10").budget, 1800.0);
+        assert_eq!(Conf::at(&conn, "2022-07-01").budget, 2000.0);
+        assert_eq!(Conf::at(&conn, "2021-01-01").weight_unit, WeightUnit::Lb);
+        // the value of 2022-06-01 took effect without being stored again
+        assert_eq!(Conf::from_db(&conn).budget, 2000.0);
+        Conf::store(&conn, "budget", "2100", Some("9999-01-01"), "2022-05-10").unwrap();
+        assert_eq!(Conf::from_db(&conn).budget, 2000.0);
+    }
+}
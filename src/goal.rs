@@ -0,0 +1,170 @@
+use crate::conf::Conf;
+use crate::db::Database;
+use crate::stats::{daily_totals, KCAL_PER_KG};
+use crate::units::Units;
+use crate::{parse_date, to_year_month_day, weight, weights_between};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{Duration, NaiveDate};
+use r2d2_sqlite::rusqlite::Connection;
+use serde::Serialize;
+
+/// Number of days of intake used to compute the average deficit
+const DEFICIT_WINDOW: i64 = 28;
+
+/// Projections further than about 100 years have no date
+const MAX_PROJECTION_DAYS: f64 = 36525.0;
+
+#[derive(Serialize, Debug)]
+pub struct Projection {
+    /// Weight change per week, negative when losing weight
+    rate_per_week: f64,
+    /// Missing when the rate does not go towards the goal or is too slow to project
+    projected_date: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GoalReport {
+    goal_weight: f64,
+    goal_date: Option<String>,
+    /// Latest value of the weight trend
+    current_weight: Option<f64>,
+    remaining: Option<f64>,
+    by_trend: Option<Projection>,
+    /// Average of metabolism minus intake over the logged days of the last 4 weeks
+    avg_deficit: Option<f64>,
+    by_deficit: Option<Projection>,
+    /// Weight change per week needed to reach the goal at goal_date
+    required_rate_per_week: Option<f64>,
+}
+
+/// When the goal is reached going at `rate_per_week` from `today`
+pub fn project(remaining: f64, rate_per_week: f64, today: NaiveDate) -> Projection {
+    let projected_date = if remaining == 0.0 {
+        Some(to_year_month_day(&today))
+    } else if rate_per_week != 0.0 && remaining.signum() == rate_per_week.signum() {
+        let days = (remaining / rate_per_week * 7.0).ceil();
+        if days <= MAX_PROJECTION_DAYS {
+            today
+                .checked_add_signed(Duration::days(days as i64))
+                .map(|d| to_year_month_day(&d))
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    Projection {
+        rate_per_week,
+        projected_date,
+    }
+}
+
+impl GoalReport {
+    fn in_units(self, units: Units) -> Self {
+        let weight = |x: Option<f64>| x.map(|x| units.weight(x));
+        let projection = |p: Option<Projection>| {
+            p.map(|p| Projection {
+                rate_per_week: units.weight(p.rate_per_week),
+                ..p
+            })
+        };
+        Self {
+            goal_weight: units.weight(self.goal_weight),
+            current_weight: weight(self.current_weight),
+            remaining: weight(self.remaining),
+            by_trend: projection(self.by_trend),
+            avg_deficit: self.avg_deficit.map(|x| units.energy(x)),
+            by_deficit: projection(self.by_deficit),
+            required_rate_per_week: weight(self.required_rate_per_week),
+            ..self
+        }
+    }
+}
+
+fn avg_deficit(conn: &Connection, today: NaiveDate) -> Option<f64> {
+    let from = today - Duration::days(DEFICIT_WINDOW - 1);
+    let totals = daily_totals(conn, &to_year_month_day(&from), &to_year_month_day(&today));
+    if totals.is_empty() {
+        return None;
+    }
+    let sum: f64 = totals
+        .iter()
+        .map(|(date, total)| Conf::at(conn, date).metabolism - total)
+        .sum();
+    Some(sum / totals.len() as f64)
+}
+
+fn goal_report(conn: &Connection, conf: &Conf, goal_weight: f64) -> GoalReport {
+    let today = conf.today();
+    let weights = weights_between(conn, "0000-01-01", "9999-12-31");
+    let trend = weight::trend(&weights, conf.weight_smoothing);
+    let current_weight = trend.last().map(|x| x.1);
+    let remaining = current_weight.map(|w| goal_weight - w);
+
+    let by_trend = remaining
+        .zip(weight::weekly_rate(&trend))
+        .map(|(remaining, rate)| project(remaining, rate, today));
+
+    let avg_deficit = avg_deficit(conn, today);
+    let by_deficit = remaining
+        .zip(avg_deficit)
+        .map(|(remaining, deficit)| project(remaining, -deficit * 7.0 / KCAL_PER_KG, today));
+
+    let required_rate_per_week = conf
+        .goal_date
+        .as_deref()
+        .and_then(parse_date)
+        .zip(remaining)
+        .and_then(|(goal_date, remaining)| {
+            let days = (goal_date - today).num_days();
+            (days > 0).then(|| remaining / days as f64 * 7.0)
+        });
+
+    GoalReport {
+        goal_weight,
+        goal_date: conf.goal_date.clone(),
+        current_weight,
+        remaining,
+        by_trend,
+        avg_deficit,
+        by_deficit,
+        required_rate_per_week,
+    }
+}
+
+pub async fn goal(Extension(db): Extension<Database>) -> impl IntoResponse {
+    tracing::info!("getting goal projection");
+    let conn = db.connection().expect("could not get connection");
+    let conf = Conf::from_db(&conn);
+    match conf.goal_weight {
+        Some(goal_weight) => (
+            StatusCode::OK,
+            Json(Some(
+                goal_report(&conn, &conf, goal_weight).in_units(Units::of(&conf)),
+            )),
+        ),
+        None => (StatusCode::NOT_FOUND, Json(None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project() {
+        let today = NaiveDate::from_ymd(2022, 1, 1);
+        let p = project(-5.0, -0.5, today);
+        assert_eq!(p.projected_date.as_deref(), Some("2022-03-12"));
+        assert_eq!(project(-5.0, 0.5, today).projected_date, None);
+        assert_eq!(project(2.0, 0.0, today).projected_date, None);
+        assert_eq!(project(-5.0, -1e-16, today).projected_date, None);
+        assert_eq!(project(-5.0, -0.0001, today).projected_date, None);
+        assert_eq!(
+            project(0.0, 0.1, today).projected_date.as_deref(),
+            Some("2022-01-01")
+        );
+    }
+}
@@ -1,18 +1,29 @@
+mod bmr;
+mod budget;
+mod conf;
 mod db;
+mod goal;
+mod metrics;
 mod migrate;
 mod search;
+mod stats;
+mod streaks;
+mod units;
+mod weight;
 
 use crate::search::SearchItem;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use axum::http::header::CONTENT_TYPE;
 use axum::response::{AppendHeaders, Html};
+use axum::routing::put;
 use axum::{
     http::StatusCode,
     response::IntoResponse,
     routing::{delete, get, post},
     Extension, Json, Router,
 };
-use chrono::{Datelike, Duration, NaiveDate, Utc};
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use conf::{Conf, ConfError};
 use db::Database;
 use include_dir::{include_dir, Dir};
 use r2d2_sqlite::rusqlite::{params, Connection, Error};
@@ -21,8 +32,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::Add;
-use axum::routing::put;
+use streaks::DayStreak;
 use tracing::log;
+use units::Units;
 
 pub static MIGRATIONS: Dir = include_dir!("migrations");
 
@@ -33,31 +45,87 @@ struct Item {
     calories: f64,
     multiplier: f64,
     timestamp: u64,
+    /// Local HH:MM of the timestamp in the conf timezone
+    time: String,
+}
+
+#[derive(Serialize)]
+struct DatedItem {
+    date: String,
+    #[serde(flatten)]
+    item: Item,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct ItemSearch {
+    /// Case insensitive substring of the name
+    q: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    /// Bounds on calories * multiplier
+    min_cal: Option<f64>,
+    max_cal: Option<f64>,
+    limit: Option<u32>,
+}
+
+/// Longest range accepted by /api/range, in days
+const MAX_RANGE_DAYS: i64 = 366;
+
+#[derive(Debug, Deserialize)]
+struct DateRange {
+    /// YYYY-MM-DD, both included
+    from: String,
+    to: String,
 }
 
 #[derive(Serialize, Default)]
 struct WeightHistory {
     /// Contains the weights of the last X days
     weights: Vec<(String, f64)>,
+    /// Smoothed weights at the same dates, see the weight_smoothing conf
+    trend: Vec<(String, f64)>,
+    /// Change of the trend over the last week
+    weekly_rate: Option<f64>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct Summary {
+    #[serde(flatten)]
+    day: DaySummary,
+    conf: Conf,
+}
+
+/// Summary without the conf, as listed for each day of a range
+#[derive(Serialize)]
+struct DaySummary {
     total: f64,
     items: Vec<Item>,
     date: String,
-    conf: HashMap<String, String>,
+    /// Budget in effect on that date, see budget_schedule
+    budget: f64,
     weight: Option<f64>,
+    /// Energy stored in one weight unit of body fat, in the conf units
+    energy_per_weight: f64,
+    /// Budget minus total
+    budget_left: f64,
+    /// Metabolism minus total, positive when eating less than burnt
+    deficit: f64,
+    /// Body fat change estimated from the deficit, negative when losing
+    fat_change: f64,
 }
 
-impl Default for Summary {
+impl Default for DaySummary {
     fn default() -> Self {
         Self {
             total: 0.0,
             items: vec![],
             date: "".to_string(),
-            conf: Default::default(),
+            budget: 0.0,
             weight: None,
+            energy_per_weight: 0.0,
+            budget_left: 0.0,
+            deficit: 0.0,
+            fat_change: 0.0,
         }
     }
 }
@@ -66,6 +134,8 @@ impl Default for Summary {
 struct AddWeight {
     date: String,
     weight: f64,
+    /// Unix timestamp of the weigh-in, defaults to now
+    timestamp: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,6 +143,39 @@ struct EditItem {
     name: Option<String>,
     calories: Option<f64>,
     multiplier: Option<f64>,
+    /// Local HH:MM on the item's date
+    time: Option<String>,
+    /// YYYY-MM-DD, moves the item to that day keeping its time of day
+    date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp {
+    Add(AddItem),
+    Edit {
+        id: u64,
+        #[serde(flatten)]
+        item: EditItem,
+    },
+    Delete {
+        id: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct BatchResult {
+    /// Id of the item, for added items only when the batch was applied
+    id: Option<u64>,
+    /// 201 for added items, 200 for other successful operations
+    status: u16,
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveItems {
+    ids: Vec<u64>,
+    /// YYYY-MM-DD
+    date: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -80,12 +183,10 @@ struct AddItem {
     name: String,
     calories: f64,
     multiplier: f64,
-    date: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct RemoveItem {
-    id: u64,
+    /// YYYY-MM-DD, defaults to today in the conf timezone
+    date: Option<String>,
+    /// Local HH:MM on that date, defaults to now
+    time: Option<String>,
 }
 
 #[tokio::main]
@@ -115,7 +216,13 @@ async fn main() {
     );
 
     migrate::migrate(&db.0, &MIGRATIONS).expect("could not run migrations");
-    let matcher = Searcher::new(&*db.connection().expect("could not get connection"));
+    let matcher = match std::env::var("SEARCH_BACKEND").as_deref() {
+        Ok("fts") => {
+            tracing::info!("using the sqlite fts5 search backend");
+            Searcher::new_fts(db.0.clone())
+        }
+        _ => Searcher::new(&db.connection().expect("could not get connection")),
+    };
 
     let app = Router::new()
         .route("/", get(root))
@@ -123,14 +230,53 @@ async fn main() {
         .route("/uplot.css", get(uplotcss))
         .route("/icon.ico", get(icon))
         .route("/api/conf", get(get_conf).post(set_conf))
+        .route("/api/conf/history", get(get_conf_history))
+        .route(
+            "/api/budget_schedule",
+            get(budget::get_schedule).post(budget::set_schedule),
+        )
+        .route(
+            "/api/budget_schedule/:effective_from",
+            delete(budget::remove_schedule),
+        )
         .route("/api/weight", post(add_weight))
+        .route("/api/bmr", get(bmr::bmr))
+        .route("/api/goal", get(goal::goal))
         .route("/api/weight_history/:after_date", get(weight_history))
+        .route(
+            "/api/metrics",
+            get(metrics::get_metrics).post(metrics::set_metric),
+        )
+        .route("/api/measurement", post(metrics::add_measurement))
+        .route(
+            "/api/measurement/:metric/:date",
+            delete(metrics::remove_measurement),
+        )
+        .route(
+            "/api/measurement/:metric/:date/readings",
+            get(metrics::readings),
+        )
+        .route("/api/reading/:id", delete(metrics::remove_reading))
+        .route(
+            "/api/measurement_history/:metric/:after_date",
+            get(metrics::measurement_history),
+        )
         .route("/api/item", post(add_item))
+        .route("/api/items", get(search_items))
+        .route("/api/items/move", post(move_items))
+        .route("/api/items/batch", post(batch))
         .route("/api/item/:id", delete(remove_item).put(edit_item))
         .route("/api/item/:id/plus1", put(plus_one))
         .route("/api/autocomplete/:qry", get(autocomplete))
         .route("/api/summary/:date", get(summary))
+        .route("/api/summary/:date/order", put(order_items))
+        .route("/api/range", get(range))
         .route("/api/calendar_data/:date", get(calendar_data))
+        .route("/api/stats", get(stats::stats))
+        .route("/api/stats/heatmap/:year", get(stats::heatmap))
+        .route("/api/stats/streaks", get(streaks::streaks))
+        .route("/api/stats/top_foods", get(stats::get_top_foods))
+        .route("/api/stats/tdee", get(stats::tdee).post(stats::apply_tdee))
         .layer(Extension(matcher))
         .layer(db);
 
@@ -178,15 +324,93 @@ async fn icon() -> impl IntoResponse {
     )
 }
 
+#[derive(Deserialize)]
+struct AutocompleteParams {
+    /// Also return every distinct calorie value logged for each name
+    #[serde(default)]
+    variants: bool,
+}
+
 async fn autocomplete(
     Path(qry): Path<String>,
+    Query(params): Query<AutocompleteParams>,
+    Extension(db): Extension<Database>,
     Extension(search): Extension<Searcher>,
 ) -> impl IntoResponse {
     tracing::info!("autocomplete: {}", &qry);
-    let res = search.search(&qry);
+    let units = Units::from_db(&db.connection().expect("could not get connection"));
+    let mut res = search.search(&qry, params.variants);
+    for r in &mut res {
+        r.calories = units.energy(r.calories);
+        r.min_calories = units.energy(r.min_calories);
+        r.max_calories = units.energy(r.max_calories);
+        for v in r.variants.iter_mut().flatten() {
+            v.calories = units.energy(v.calories);
+        }
+    }
     (StatusCode::OK, Json(res))
 }
 
+/// HH:MM
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+/// Timestamp of the optional HH:MM time on the date, now when missing.
+/// None if the time is invalid.
+fn item_timestamp(conf: &Conf, date: NaiveDate, time: Option<&str>) -> Option<i64> {
+    match time {
+        Some(time) => item_timestamp_at(conf, date, parse_time(time)?),
+        None => Some(Utc::now().timestamp()),
+    }
+}
+
+/// See Conf::timestamp_at, None before 1970 as item timestamps are unsigned
+fn item_timestamp_at(conf: &Conf, day: NaiveDate, time: NaiveTime) -> Option<i64> {
+    conf.timestamp_at(day, time).filter(|&t| t >= 0)
+}
+
+/// Sets the date and local time of the item, keeping the current ones when missing.
+/// The item loses its position when changing day. NOT_FOUND if it does not exist,
+/// BAD_REQUEST if the time does not exist on that day (DST gap) or is before 1970.
+fn reschedule_item(
+    conn: &Connection,
+    conf: &Conf,
+    id: u64,
+    date: Option<NaiveDate>,
+    time: Option<NaiveTime>,
+) -> Result<(), StatusCode> {
+    let (old_date, old_timestamp): (String, i64) = match conn.query_row(
+        "SELECT date, timestamp FROM items WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ) {
+        Ok(x) => x,
+        Err(Error::QueryReturnedNoRows) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => panic!("could not get item: {}", e),
+    };
+    let day = date
+        .or_else(|| parse_date(&old_date))
+        .unwrap_or_else(|| conf.day_of(old_timestamp));
+    let time = time.unwrap_or_else(|| conf.local_time(old_timestamp));
+    let timestamp = item_timestamp_at(conf, day, time).ok_or(StatusCode::BAD_REQUEST)?;
+    conn.execute(
+        "UPDATE items SET date = ?1, timestamp = ?2,
+        position = CASE WHEN date = ?1 THEN position END WHERE id = ?3;",
+        params![to_year_month_day(&day), timestamp, id],
+    )
+    .expect("could not reschedule item");
+    Ok(())
+}
+
+/// YYYY-MM-DD date that exists
+fn valid_date(date: &str) -> Option<NaiveDate> {
+    if !check_date(date) {
+        return None;
+    }
+    parse_date(date)
+}
+
 fn check_date(date: &str) -> bool {
     if date.len() != 10 {
         return false;
@@ -198,20 +422,51 @@ fn check_date(date: &str) -> bool {
     true
 }
 
+/// `date` is YYYY-MM-DD or "today" in the conf timezone
 async fn summary(
     Path(date): Path<String>,
     Extension(db): Extension<Database>,
 ) -> impl IntoResponse {
     tracing::info!("getting historical summary");
+    let conn = db.connection().expect("could not get connection");
+    let date = if date == "today" {
+        to_year_month_day(&today(&conn))
+    } else {
+        date
+    };
     // YYYY-MM-DD validation
     if !check_date(&date) {
         return (StatusCode::BAD_REQUEST, Json(Summary::default()));
     }
-    let conn = db.connection().expect("could not get connection");
-    let summary = mk_summary(&*conn, date);
+    let summary = mk_summary(&conn, date);
     (StatusCode::OK, Json(summary))
 }
 
+/// Summary of every day of the range, including the days without items
+async fn range(
+    Query(range): Query<DateRange>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting summaries of {:?}", range);
+    let (from, to) = match (parse_date(&range.from), parse_date(&range.to)) {
+        (Some(from), Some(to)) if check_date(&range.from) && check_date(&range.to) => (from, to),
+        _ => return (StatusCode::BAD_REQUEST, Json(vec![])),
+    };
+    let n_days = (to - from).num_days() + 1;
+    if !(1..=MAX_RANGE_DAYS).contains(&n_days) {
+        return (StatusCode::BAD_REQUEST, Json(vec![]));
+    }
+    let conn = db.connection().expect("could not get connection");
+    let summaries: Vec<DaySummary> = (0..n_days)
+        .map(|i| {
+            let date = to_year_month_day(&(from + Duration::days(i)));
+            let conf = Conf::at(&conn, &date);
+            mk_day_summary(&conn, date, &conf)
+        })
+        .collect();
+    (StatusCode::OK, Json(summaries))
+}
+
 async fn weight_history(
     Path(after_date): Path<String>,
     Extension(db): Extension<Database>,
@@ -223,29 +478,127 @@ async fn weight_history(
     }
     let after_date = after_date.unwrap();
     let conn = db.connection().expect("could not get connection");
+    let after_date = to_year_month_day(&after_date);
+    let all_weights = weights_between(&conn, "0000-01-01", "9999-12-31");
+    let conf = Conf::from_db(&conn);
+    let units = Units::of(&conf);
+    let (weights, trend, weekly_rate) =
+        weight::history_after(all_weights, conf.weight_smoothing, &after_date);
+    (
+        StatusCode::OK,
+        Json(WeightHistory {
+            weights: units.weights(weights),
+            trend: units.weights(trend),
+            weekly_rate: weekly_rate.map(|x| units.weight(x)),
+        }),
+    )
+}
+
+/// Most recent (date, weight)
+fn latest_weight(conn: &Connection) -> Option<(String, f64)> {
     let mut stmt = conn
-        .prepare("select date, weight from weight where date >= ? order by date")
+        .prepare_cached("select date, weight from weight order by date desc limit 1")
         .expect("could not prepare statement");
-    let mut rows = stmt
-        .query_map(&[&to_year_month_day(&after_date)], |row| {
-            Ok((row.get(0)?, row.get(1)?))
-        })
+    match stmt.query_row([], |row| Ok((row.get(0)?, row.get(1)?))) {
+        Ok(x) => Some(x),
+        Err(Error::QueryReturnedNoRows) => None,
+        Err(e) => panic!("could not get latest weight: {}", e),
+    }
+}
+
+/// Daily weights between the two YYYY-MM-DD dates included, ordered by date
+fn weights_between(conn: &Connection, from: &str, to: &str) -> Vec<(String, f64)> {
+    let mut stmt = conn
+        .prepare_cached(
+            "select date, weight from weight where date BETWEEN ?1 AND ?2 order by date",
+        )
+        .expect("could not prepare statement");
+    let rows = stmt
+        .query_map([from, to], |row| Ok((row.get(0)?, row.get(1)?)))
         .expect("could not query");
     let mut weights = vec![];
-    while let Some(row) = rows.next() {
+    for row in rows {
         let (date, weight): (String, f64) = row.expect("could not get row");
         weights.push((date, weight));
     }
-    (StatusCode::OK, Json(WeightHistory { weights }))
+    weights
+}
+
+async fn search_items(
+    Query(search): Query<ItemSearch>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("searching items {:?}", search);
+    let dates_ok = [&search.from, &search.to]
+        .iter()
+        .all(|d| d.as_deref().map(check_date).unwrap_or(true));
+    if !dates_ok {
+        return (StatusCode::BAD_REQUEST, Json(vec![]));
+    }
+    let conn = db.connection().expect("could not get connection");
+    let items = find_items(&conn, &Conf::from_db(&conn), &search);
+    (StatusCode::OK, Json(items))
+}
+
+fn find_items(conn: &Connection, conf: &Conf, search: &ItemSearch) -> Vec<DatedItem> {
+    let units = Units::of(conf);
+    let mut qry = conn
+        .prepare_cached(
+            "SELECT id, name, calories, multiplier, timestamp, date FROM items
+            WHERE (?1 IS NULL OR instr(lower(name), lower(?1)) > 0)
+              AND (?2 IS NULL OR date >= ?2)
+              AND (?3 IS NULL OR date <= ?3)
+              AND (?4 IS NULL OR calories * multiplier >= ?4)
+              AND (?5 IS NULL OR calories * multiplier <= ?5)
+            ORDER BY date DESC, timestamp DESC
+            LIMIT ?6",
+        )
+        .expect("could not prepare qry");
+    let mut rows = qry
+        .query(params![
+            search.q,
+            search.from,
+            search.to,
+            search.min_cal.map(|x| units.energy_in(x)),
+            search.max_cal.map(|x| units.energy_in(x)),
+            search.limit.unwrap_or(100),
+        ])
+        .expect("could not run qry");
+
+    let mut items = vec![];
+    while let Ok(Some(x)) = rows.next() {
+        items.push(DatedItem {
+            date: x.get_unwrap("date"),
+            item: Item {
+                id: x.get_unwrap("id"),
+                name: x.get_unwrap("name"),
+                calories: units.energy(x.get_unwrap("calories")),
+                multiplier: x.get_unwrap("multiplier"),
+                timestamp: x.get_unwrap("timestamp"),
+                time: conf.time_of_day(x.get_unwrap("timestamp")),
+            },
+        });
+    }
+    items
 }
 
 fn mk_summary(conn: &Connection, date: String) -> Summary {
+    let conf = Conf::at(conn, &date);
+    Summary {
+        day: mk_day_summary(conn, date, &conf),
+        conf: Units::of(&conf).conf(conf),
+    }
+}
+
+/// `conf` is the conf in effect on the date, see Conf::at
+fn mk_day_summary(conn: &Connection, date: String, conf: &Conf) -> DaySummary {
     let mut qry = conn
         .prepare_cached(
-            "SELECT id, name, calories, multiplier, timestamp FROM items WHERE date = ?1",
+            "SELECT id, name, calories, multiplier, timestamp FROM items WHERE date = ?1
+            ORDER BY position IS NULL, position, timestamp",
         )
         .expect("could not prepare qry");
-    let mut rows = qry.query(&[&date]).expect("could not run qry");
+    let mut rows = qry.query([&date]).expect("could not run qry");
 
     let mut items = vec![];
     while let Ok(Some(x)) = rows.next() {
@@ -255,32 +608,58 @@ fn mk_summary(conn: &Connection, date: String) -> Summary {
             calories: x.get("calories").unwrap(),
             multiplier: x.get("multiplier").unwrap(),
             timestamp: x.get("timestamp").unwrap(),
+            time: String::new(),
         });
     }
 
-    items.sort_by_key(|x| x.timestamp);
-
     let mut qry_weight = conn
         .prepare_cached("SELECT weight FROM weight WHERE date = ?1")
         .expect("could not prepare qry_weight");
-    let weight: Option<f64> = match qry_weight.query_row(&[&date], |row| row.get(0)) {
+    let weight: Option<f64> = match qry_weight.query_row([&date], |row| row.get(0)) {
         Ok(x) => Some(x),
         Err(Error::QueryReturnedNoRows) => None,
         Err(_) => panic!("could not get weight for the day"),
     };
 
-    Summary {
-        total: items.iter().map(|x| x.calories * x.multiplier).sum(),
+    let budget = budget::daily_budget(conn, &date);
+
+    let units = Units::of(conf);
+    for item in &mut items {
+        item.calories = units.energy(item.calories);
+        item.time = conf.time_of_day(item.timestamp as i64);
+    }
+    let total = items
+        .iter()
+        .fold(0.0, |acc, x| acc + x.calories * x.multiplier);
+    let budget = units.energy(budget);
+    let deficit = units.energy(conf.metabolism) - total;
+    DaySummary {
+        total,
         items,
         date,
-        conf: get_conf_from_db(&conn),
-        weight,
+        budget,
+        weight: weight.map(|w| units.weight(w)),
+        energy_per_weight: units.energy_per_weight(),
+        budget_left: budget - total,
+        deficit,
+        fat_change: -deficit / units.energy_per_weight(),
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 pub struct CalendarItem {
     total: f64,
+    /// Budget in effect on that day, see budget_schedule
+    budget: f64,
+    /// Metabolism in effect on that day, see Conf::at
+    metabolism: f64,
+    over_budget: bool,
+    item_count: u32,
+    /// Whether the day has items, days with only a weight are also listed
+    logged: bool,
+    weight: Option<f64>,
+    /// Streaks ending on that day
+    streak: DayStreak,
 }
 
 #[derive(Serialize, Default)]
@@ -288,24 +667,25 @@ pub struct CalendarData(HashMap<String, CalendarItem>);
 
 /// date is encoded as YYYY-MM-DD or YYYY-MM
 fn parse_date(date: &str) -> Option<NaiveDate> {
-    let v: Vec<&str> = date.split("-").collect();
+    let v: Vec<&str> = date.split('-').collect();
     let year: i32 = v[0].parse().ok()?;
-    if year < 1000 || year > 9999 {
+    if !(1000..=9999).contains(&year) {
         return None;
     }
-    let month = v[1].parse().ok()?;
-    if month < 1 || month > 12 {
+    let month = v.get(1)?.parse().ok()?;
+    if !(1..=12).contains(&month) {
         return None;
     }
     let day = if v.len() == 3 { v[2].parse().ok()? } else { 1 };
-    if day < 1 || day > 31 {
+    if !(1..=31).contains(&day) {
         return None;
     }
-    Some(NaiveDate::from_ymd(year, month, day))
+    NaiveDate::from_ymd_opt(year, month, day)
 }
 
-fn to_year_month(d: &NaiveDate) -> String {
-    format!("{:04}-{:02}", d.year(), d.month())
+/// Current day in the conf timezone, see Conf::day_of
+fn today(conn: &Connection) -> NaiveDate {
+    Conf::from_db(conn).today()
 }
 
 fn to_year_month_day(d: &NaiveDate) -> String {
@@ -328,6 +708,64 @@ pub fn get_days_from_month(year: i32, month: u32) -> u32 {
     .num_days() as u32
 }
 
+/// Days shown by the calendar of the month of `d`: from the monday of the week of `d`
+/// to the sunday of the week of the last day of the month, both included
+fn calendar_range(d: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let monday_of_first_week = d.add(-Duration::days(d.weekday().num_days_from_monday() as i64));
+    let last_day = NaiveDate::from_ymd(
+        d.year(),
+        d.month(),
+        get_days_from_month(d.year(), d.month()),
+    );
+    let sunday_of_last_week = last_day.add(Duration::days(
+        6 - last_day.weekday().num_days_from_monday() as i64,
+    ));
+    (monday_of_first_week, sunday_of_last_week)
+}
+
+/// Calendar items of the days between the two dates included that have items or a weight
+fn calendar_between(
+    conn: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> HashMap<String, CalendarItem> {
+    let (from_s, to_s) = (to_year_month_day(&from), to_year_month_day(&to));
+    let units = Units::from_db(conn);
+    let mut data: HashMap<String, CalendarItem> = HashMap::with_capacity(42);
+
+    for day in budget::logged_days(conn, &from_s, &to_s) {
+        data.insert(
+            day.date,
+            CalendarItem {
+                total: units.energy(day.total),
+                budget: units.energy(day.budget),
+                over_budget: day.total > day.budget,
+                metabolism: units.energy(day.metabolism),
+                item_count: day.n_items,
+                logged: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    for (date, weight) in weights_between(conn, &from_s, &to_s) {
+        data.entry(date).or_default().weight = Some(units.weight(weight));
+    }
+
+    let streaks: HashMap<NaiveDate, DayStreak> = streaks::streaks_until(conn, &to_s)
+        .into_iter()
+        .filter(|d| d.0 >= from)
+        .map(|(date, _, streak)| (date, streak))
+        .collect();
+
+    for (date, item) in data.iter_mut() {
+        item.streak = parse_date(date)
+            .and_then(|d| streaks.get(&d).copied())
+            .unwrap_or_default();
+    }
+    data
+}
+
 async fn calendar_data(
     Path(date): Path<String>,
     Extension(db): Extension<Database>,
@@ -340,35 +778,12 @@ async fn calendar_data(
     let d = d.unwrap();
     let conn = db.connection().expect("could not get connection");
 
-    let monday_of_first_week = d.add(-Duration::days(d.weekday().num_days_from_monday() as i64));
-    let last_day = d.add(Duration::days(
-        get_days_from_month(d.year(), d.month()) as i64
-    ));
-    let sunday_of_last_week = last_day.add(Duration::days(
-        6 - last_day.weekday().num_days_from_monday() as i64,
-    ));
+    let (monday_of_first_week, sunday_of_last_week) = calendar_range(d);
 
     log::info!("monday_of_first_week: {}", monday_of_first_week);
     log::info!("sunday_of_last_week: {}", sunday_of_last_week);
 
-    let mut qry = conn.prepare_cached("SELECT date, sum(calories * multiplier) as total FROM items WHERE date BETWEEN ?1 AND ?2 GROUP BY date").expect("could not prepare qry");
-    let mut rows = qry
-        .query(params![
-            to_year_month(&monday_of_first_week),
-            to_year_month(&sunday_of_last_week)
-        ])
-        .expect("could not execute qry");
-
-    let mut data = HashMap::with_capacity(32);
-    while let Ok(Some(row)) = rows.next() {
-        data.insert(
-            row.get_unwrap("date"),
-            CalendarItem {
-                total: row.get_unwrap("total"),
-            },
-        );
-    }
-
+    let data = calendar_between(&conn, monday_of_first_week, sunday_of_last_week);
     (StatusCode::OK, Json(CalendarData(data)))
 }
 
@@ -376,40 +791,49 @@ async fn calendar_data(
 pub struct ConfSet {
     key: String,
     value: String,
+    /// YYYY-MM-DD, only for versioned keys. Defaults to today.
+    effective_from: Option<String>,
 }
 
 async fn set_conf(
     Extension(db): Extension<Database>,
     Json(confset): Json<ConfSet>,
-) -> impl IntoResponse {
+) -> Result<StatusCode, ConfError> {
     tracing::info!("setting conf: {} = {}", &confset.key, &confset.value);
+    if let Some(d) = &confset.effective_from {
+        if !check_date(d) || parse_date(d).is_none() {
+            return Err(ConfError::Invalid {
+                key: "effective_from".to_string(),
+                reason: "expected YYYY-MM-DD".to_string(),
+            });
+        }
+    }
     let conn = db.connection().expect("could not get connection");
-    conn.execute(
-        "INSERT INTO conf (key, value) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET value = ?2;",
-        params![confset.key, confset.value],
-    )
-    .expect("could not prepare qry");
-    StatusCode::CREATED
+    let value = Units::from_db(&conn).conf_value_in(&confset.key, &confset.value);
+    Conf::store(
+        &conn,
+        &confset.key,
+        &value,
+        confset.effective_from.as_deref(),
+        &to_year_month_day(&today(&conn)),
+    )?;
+    Ok(StatusCode::CREATED)
 }
 
-fn get_conf_from_db(conn: &Connection) -> HashMap<String, String> {
-    let mut qry = conn
-        .prepare("SELECT key, value FROM conf;")
-        .expect("could not prepare qry");
-    let mut rows = qry.query([]).expect("could not do query");
-    let mut v: HashMap<String, String> = HashMap::new();
+async fn get_conf(Extension(db): Extension<Database>) -> impl IntoResponse {
+    tracing::info!("getting conf");
+    let conn = db.connection().expect("could not get connection");
 
-    while let Ok(Some(row)) = rows.next() {
-        v.insert(row.get_unwrap("key"), row.get_unwrap("value"));
-    }
-    v
+    let conf = Conf::from_db(&conn);
+    (StatusCode::CREATED, Json(Units::of(&conf).conf(conf)))
 }
 
-async fn get_conf(Extension(db): Extension<Database>) -> impl IntoResponse {
-    tracing::info!("getting conf");
+async fn get_conf_history(Extension(db): Extension<Database>) -> impl IntoResponse {
+    tracing::info!("getting conf history");
     let conn = db.connection().expect("could not get connection");
 
-    (StatusCode::CREATED, Json(get_conf_from_db(&conn)))
+    let history = Units::from_db(&conn).conf_history(Conf::history(&conn));
+    (StatusCode::OK, Json(history))
 }
 
 async fn add_weight(
@@ -417,13 +841,17 @@ async fn add_weight(
     Extension(db): Extension<Database>,
 ) -> impl IntoResponse {
     tracing::info!("adding weight {:?}", &weight);
+    if !check_date(&weight.date) {
+        return StatusCode::BAD_REQUEST;
+    }
     let conn = db.connection().expect("could not get connection");
-    conn.execute(
-        "INSERT INTO weight (date, weight) VALUES (?1, ?2) 
-        ON CONFLICT (date) DO UPDATE SET weight=?2;",
-        params![weight.date, weight.weight],
-    )
-    .expect("could not insert weight into db");
+    metrics::insert_measurement(
+        &conn,
+        metrics::WEIGHT,
+        &weight.date,
+        Units::from_db(&conn).weight_in(weight.weight),
+        weight.timestamp,
+    );
     StatusCode::OK
 }
 
@@ -435,35 +863,76 @@ async fn edit_item(
 ) -> impl IntoResponse {
     tracing::info!("editing item {:?}", item);
     let conn = db.connection().expect("could not get connection");
+    match update_item(&conn, &Conf::from_db(&conn), id, item) {
+        Ok(change) => {
+            search.apply(vec![change]);
+            StatusCode::OK
+        }
+        Err(status) => status,
+    }
+}
+
+fn update_item(
+    conn: &Connection,
+    conf: &Conf,
+    id: u64,
+    item: EditItem,
+) -> Result<search::Change, StatusCode> {
+    let calories = item.calories.map(|x| Units::of(conf).energy_in(x));
+    let date = item.date.as_deref().map(valid_date);
+    let time = item.time.as_deref().map(parse_time);
+    if date == Some(None) || time == Some(None) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if date.is_some() || time.is_some() {
+        reschedule_item(conn, conf, id, date.flatten(), time.flatten())?;
+    }
     let n_updated = conn
         .execute(
             "UPDATE items SET name = COALESCE(?1, name), calories = COALESCE(?2, calories), multiplier = COALESCE(?3, multiplier) WHERE id = ?4;",
             params![
             item.name,
-            item.calories,
+            calories,
             item.multiplier,
             id,
         ])
         .expect("could not execute update item qry");
     if n_updated == 0 {
-        return StatusCode::NOT_FOUND;
+        return Err(StatusCode::NOT_FOUND);
     }
-    search.update(id, item.name, item.calories);
-    StatusCode::OK
+    Ok(search::Change::Update(id, item.name, calories))
 }
 
-async fn plus_one(
-    Path(id): Path<u64>,
+/// Moves all the items to the date, or none of them if one cannot be moved
+async fn move_items(
+    Json(m): Json<MoveItems>,
     Extension(db): Extension<Database>,
 ) -> impl IntoResponse {
+    tracing::info!("moving items {:?}", m);
+    let date = match valid_date(&m.date) {
+        Some(d) => d,
+        None => return StatusCode::BAD_REQUEST,
+    };
+    let mut conn = db.connection().expect("could not get connection");
+    let conf = Conf::from_db(&conn);
+    let tx = conn.transaction().expect("could not start transaction");
+    for &id in &m.ids {
+        if let Err(status) = reschedule_item(&tx, &conf, id, Some(date), None) {
+            return status;
+        }
+    }
+    tx.commit().expect("could not commit moved items");
+    StatusCode::OK
+}
+
+async fn plus_one(Path(id): Path<u64>, Extension(db): Extension<Database>) -> impl IntoResponse {
     tracing::info!("plus one item {:?}", id);
     let conn = db.connection().expect("could not get connection");
     let n_updated = conn
         .execute(
             "UPDATE items SET multiplier = multiplier + 1 WHERE id = ?1;",
-            params![
-            id,
-        ])
+            params![id,],
+        )
         .expect("could not execute update item qry");
     if n_updated == 0 {
         return StatusCode::NOT_FOUND;
@@ -477,33 +946,85 @@ async fn add_item(
     Extension(search): Extension<Searcher>,
 ) -> impl IntoResponse {
     tracing::info!("adding item {:?}", item);
-    if !check_date(&item.date) {
-        return StatusCode::BAD_REQUEST;
-    }
     let conn = db.connection().expect("could not get connection");
+    match insert_item(&conn, &Conf::from_db(&conn), item) {
+        Ok((_, change)) => {
+            search.apply(vec![change]);
+            StatusCode::CREATED
+        }
+        Err(status) => status,
+    }
+}
+
+fn insert_item(
+    conn: &Connection,
+    conf: &Conf,
+    item: AddItem,
+) -> Result<(u64, search::Change), StatusCode> {
+    let date = match item.date.as_deref().map(valid_date) {
+        Some(Some(d)) => d,
+        Some(None) => return Err(StatusCode::BAD_REQUEST),
+        None => conf.today(),
+    };
+    let timestamp = match item_timestamp(conf, date, item.time.as_deref()) {
+        Some(t) => t,
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+    let calories = Units::of(conf).energy_in(item.calories);
     let id = conn
         .query_row(
             "INSERT INTO items (name, calories, multiplier, date, timestamp) VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id;",
             params![
             item.name,
-            item.calories,
+            calories,
             item.multiplier,
-            item.date,
-            Utc::now().timestamp()
+            to_year_month_day(&date),
+            timestamp
         ]
             , |row| {
                 row.get("id")
             },
         )
         .expect("could not prepare qry");
-    search.insert(
+    Ok((
         id,
-        SearchItem {
-            name: item.name,
-            calories: item.calories,
-        },
-    );
-    StatusCode::CREATED
+        search::Change::Insert(
+            id,
+            SearchItem {
+                name: item.name,
+                calories,
+            },
+        ),
+    ))
+}
+
+/// Sets the order of the items of the day to the given ids, the other items go after them
+async fn order_items(
+    Path(date): Path<String>,
+    Json(ids): Json<Vec<u64>>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("ordering items of {}: {:?}", date, ids);
+    if !check_date(&date) {
+        return StatusCode::BAD_REQUEST;
+    }
+    let mut conn = db.connection().expect("could not get connection");
+    let tx = conn.transaction().expect("could not start transaction");
+    tx.execute("UPDATE items SET position = NULL WHERE date = ?1;", [&date])
+        .expect("could not reset positions");
+    for (position, id) in ids.iter().enumerate() {
+        let n_updated = tx
+            .execute(
+                "UPDATE items SET position = ?1 WHERE id = ?2 AND date = ?3;",
+                params![position, id, date],
+            )
+            .expect("could not set position");
+        if n_updated == 0 {
+            return StatusCode::BAD_REQUEST;
+        }
+    }
+    tx.commit().expect("could not commit positions");
+    StatusCode::OK
 }
 
 async fn remove_item(
@@ -513,12 +1034,83 @@ async fn remove_item(
 ) -> impl IntoResponse {
     tracing::info!("removing item {}", id);
     let conn = db.connection().expect("could not get connection");
-    if let Err(e) = conn.execute("DELETE FROM items WHERE id = ?1;", &[&id]) {
-        tracing::error!("error in remove_item: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR;
+    match delete_item(&conn, id) {
+        Ok(change) => {
+            search.apply(vec![change]);
+            StatusCode::CREATED
+        }
+        Err(status) => status,
+    }
+}
+
+fn delete_item(conn: &Connection, id: u64) -> Result<search::Change, StatusCode> {
+    match conn.execute("DELETE FROM items WHERE id = ?1;", [&id]) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(search::Change::Remove(id)),
+        Err(e) => {
+            tracing::error!("error in delete_item: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
-    search.remove(id);
-    StatusCode::CREATED
+}
+
+/// Applies every operation in a single transaction. If one of them fails nothing is
+/// applied and the response is a 400 with the result of each operation.
+async fn batch(
+    Json(ops): Json<Vec<BatchOp>>,
+    Extension(db): Extension<Database>,
+    Extension(search): Extension<Searcher>,
+) -> impl IntoResponse {
+    tracing::info!("running {} batch operations", ops.len());
+    let mut conn = db.connection().expect("could not get connection");
+    let conf = Conf::from_db(&conn);
+    let tx = conn.transaction().expect("could not start transaction");
+
+    let mut results = Vec::with_capacity(ops.len());
+    let mut changes = Vec::with_capacity(ops.len());
+    // indices of the results of added items
+    let mut added = vec![];
+    for op in ops {
+        let (id, res) = match op {
+            BatchOp::Add(item) => match insert_item(&tx, &conf, item) {
+                Ok((id, change)) => {
+                    added.push(results.len());
+                    (Some(id), Ok((StatusCode::CREATED, change)))
+                }
+                Err(status) => (None, Err(status)),
+            },
+            BatchOp::Edit { id, item } => (
+                Some(id),
+                update_item(&tx, &conf, id, item).map(|change| (StatusCode::OK, change)),
+            ),
+            BatchOp::Delete { id } => (
+                Some(id),
+                delete_item(&tx, id).map(|change| (StatusCode::OK, change)),
+            ),
+        };
+        let status = match res {
+            Ok((status, change)) => {
+                changes.push(change);
+                status
+            }
+            Err(status) => status,
+        };
+        results.push(BatchResult {
+            id,
+            status: status.as_u16(),
+        });
+    }
+
+    if changes.len() < results.len() {
+        // the ids were rolled back and will be reused
+        for i in added {
+            results[i].id = None;
+        }
+        return (StatusCode::BAD_REQUEST, Json(results));
+    }
+    tx.commit().expect("could not commit batch");
+    search.apply(changes);
+    (StatusCode::OK, Json(results))
 }
 
 #[cfg(test)]
@@ -526,9 +1118,23 @@ mod tests_date {
     use super::*;
     use chrono::NaiveDate;
     #[test]
-    fn test_to_year_month() {
-        assert_eq!(to_year_month(&NaiveDate::from_ymd(2021, 1, 1)), "2021-01");
-        assert_eq!(to_year_month(&NaiveDate::from_ymd(2021, 12, 1)), "2021-12");
+    fn test_calendar_range() {
+        // may 2022 starts on a sunday and ends on a tuesday
+        assert_eq!(
+            calendar_range(NaiveDate::from_ymd(2022, 5, 1)),
+            (
+                NaiveDate::from_ymd(2022, 4, 25),
+                NaiveDate::from_ymd(2022, 6, 5)
+            )
+        );
+        // february 2021 starts on a monday and ends on a sunday
+        assert_eq!(
+            calendar_range(NaiveDate::from_ymd(2021, 2, 1)),
+            (
+                NaiveDate::from_ymd(2021, 2, 1),
+                NaiveDate::from_ymd(2021, 2, 28)
+            )
+        );
     }
 
     #[test]
@@ -547,3 +1153,179 @@ mod tests_date {
         );
     }
 }
+
+#[cfg(test)]
+mod tests_items {
+    use super::*;
+    use crate::migrate::test_db;
+
+    fn add(conn: &Connection, conf: &Conf, date: &str, time: &str) -> Result<u64, StatusCode> {
+        let item = AddItem {
+            name: "apple".to_string(),
+            calories: 80.0,
+            multiplier: 1.0,
+            date: Some(date.to_string()),
+            time: Some(time.to_string()),
+        };
+        insert_item(conn, conf, item).map(|(id, _)| id)
+    }
+
+    #[test]
+    fn test_reschedule_item() {
+        let conn = test_db();
+        let mut conf = Conf::default();
+        conf.set("timezone", "Europe/Paris").unwrap();
+        let id = add(&conn, &conf, "2022-03-26", "12:00").unwrap();
+        let day = NaiveDate::from_ymd(2022, 3, 27);
+        // clocks go from 02:00 to 03:00 on that day
+        assert_eq!(
+            reschedule_item(&conn, &conf, id, Some(day), parse_time("02:30")),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            reschedule_item(&conn, &conf, id + 1, Some(day), None),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            reschedule_item(
+                &conn,
+                &conf,
+                id,
+                NaiveDate::from_ymd_opt(1969, 12, 31),
+                None
+            ),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(reschedule_item(&conn, &conf, id, Some(day), None), Ok(()));
+        let (date, timestamp): (String, i64) = conn
+            .query_row(
+                "SELECT date, timestamp FROM items WHERE id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(date, "2022-03-27");
+        assert_eq!(conf.time_of_day(timestamp), "12:00");
+    }
+
+    #[test]
+    fn test_find_items() {
+        let conn = test_db();
+        for (name, calories, multiplier, date) in [
+            ("Apple", 100.0, 2.0, "2022-01-01"),
+            ("apple pie", 400.0, 1.0, "2022-01-02"),
+            ("Rice", 200.0, 1.0, "2022-01-03"),
+        ] {
+            conn.execute(
+                "INSERT INTO items (name, calories, multiplier, date, timestamp) VALUES (?1, ?2, ?3, ?4, 0);",
+                params![name, calories, multiplier, date],
+            )
+            .unwrap();
+        }
+        let mut conf = Conf::default();
+        conf.set("energy_unit", "kJ").unwrap();
+        let names = |search: ItemSearch| -> Vec<String> {
+            find_items(&conn, &conf, &search)
+                .into_iter()
+                .map(|x| x.item.name)
+                .collect()
+        };
+
+        assert_eq!(names(ItemSearch::default()), ["Rice", "apple pie", "Apple"]);
+        let q = Some("APPLE".to_string());
+        assert_eq!(
+            names(ItemSearch {
+                q: q.clone(),
+                ..Default::default()
+            }),
+            ["apple pie", "Apple"]
+        );
+        assert_eq!(
+            names(ItemSearch {
+                from: Some("2022-01-02".to_string()),
+                to: Some("2022-01-02".to_string()),
+                ..Default::default()
+            }),
+            ["apple pie"]
+        );
+        // 200 kcal is 836.8 kJ
+        assert_eq!(
+            names(ItemSearch {
+                q: q.clone(),
+                min_cal: Some(800.0),
+                max_cal: Some(900.0),
+                ..Default::default()
+            }),
+            ["Apple"]
+        );
+        assert!(names(ItemSearch {
+            min_cal: Some(2000.0),
+            ..Default::default()
+        })
+        .is_empty());
+        assert_eq!(
+            names(ItemSearch {
+                limit: Some(1),
+                ..Default::default()
+            }),
+            ["Rice"]
+        );
+        let apple = &find_items(
+            &conn,
+            &conf,
+            &ItemSearch {
+                q,
+                limit: Some(1),
+                ..Default::default()
+            },
+        )[0];
+        assert_eq!(apple.item.calories, 400.0 * 4.184);
+        assert_eq!(apple.date, "2022-01-02");
+    }
+
+    #[test]
+    fn test_add_item_date() {
+        let conn = test_db();
+        let conf = Conf::default();
+        let item = |date: &str| AddItem {
+            name: "apple".to_string(),
+            calories: 80.0,
+            multiplier: 1.0,
+            date: Some(date.to_string()),
+            time: None,
+        };
+        assert!(insert_item(&conn, &conf, item("2022-02-28")).is_ok());
+        assert!(matches!(
+            insert_item(&conn, &conf, item("2022-02-30")),
+            Err(StatusCode::BAD_REQUEST)
+        ));
+        assert!(matches!(
+            insert_item(&conn, &conf, item("2022-2-28")),
+            Err(StatusCode::BAD_REQUEST)
+        ));
+    }
+
+    #[test]
+    fn test_item_before_1970() {
+        let conn = test_db();
+        let conf = Conf::default();
+        assert_eq!(
+            add(&conn, &conf, "1969-12-31", "23:59"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert!(add(&conn, &conf, "1970-01-01", "00:00").is_ok());
+        let summary = mk_summary(&conn, "1970-01-01".to_string());
+        assert_eq!(summary.day.items[0].timestamp, 0);
+    }
+
+    #[test]
+    fn test_delete_item() {
+        let conn = test_db();
+        let id = add(&conn, &Conf::default(), "2022-03-26", "12:00").unwrap();
+        assert!(matches!(
+            delete_item(&conn, id),
+            Ok(search::Change::Remove(x)) if x == id
+        ));
+        assert!(matches!(delete_item(&conn, id), Err(StatusCode::NOT_FOUND)));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::conf::Conf;
+use crate::db::Database;
+use crate::units::Units;
+use crate::{check_date, parse_date, to_year_month_day, weight};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::Utc;
+use r2d2_sqlite::rusqlite::{params, Connection, Error};
+use serde::{Deserialize, Serialize};
+
+/// Its unit follows the weight_unit conf instead of the metric table
+pub const WEIGHT: &str = "weight";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Metric {
+    name: String,
+    unit: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMeasurement {
+    metric: String,
+    date: String,
+    value: f64,
+    /// Unix timestamp of the reading, defaults to now
+    timestamp: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Reading {
+    id: u64,
+    timestamp: i64,
+    value: f64,
+}
+
+#[derive(Serialize, Default)]
+pub struct MeasurementHistory {
+    unit: String,
+    values: Vec<(String, f64)>,
+    /// Smoothed values at the same dates, see the weight_smoothing conf
+    trend: Vec<(String, f64)>,
+    weekly_rate: Option<f64>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn metric_unit(conn: &Connection, name: &str) -> Option<String> {
+    let mut qry = conn
+        .prepare_cached("SELECT unit FROM metric WHERE name = ?1")
+        .expect("could not prepare qry");
+    match qry.query_row([name], |row| row.get(0)) {
+        Ok(x) => Some(x),
+        Err(Error::QueryReturnedNoRows) => None,
+        Err(e) => panic!("could not get metric: {}", e),
+    }
+}
+
+/// Adds a reading of the metric for the date, the value of the day is picked
+/// from its readings by the measurement view. Returns false if the metric does not exist.
+pub fn insert_measurement(
+    conn: &Connection,
+    metric: &str,
+    date: &str,
+    value: f64,
+    timestamp: Option<i64>,
+) -> bool {
+    if metric_unit(conn, metric).is_none() {
+        return false;
+    }
+    conn.execute(
+        "INSERT INTO measurement_reading (metric, date, timestamp, value) VALUES (?1, ?2, ?3, ?4);",
+        params![
+            metric,
+            date,
+            timestamp.unwrap_or_else(|| Utc::now().timestamp()),
+            value
+        ],
+    )
+    .expect("could not insert measurement into db");
+    true
+}
+
+/// Values of the metric between the two YYYY-MM-DD dates included, ordered by date
+pub fn measurements_between(
+    conn: &Connection,
+    metric: &str,
+    from: &str,
+    to: &str,
+) -> Vec<(String, f64)> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT date, value FROM measurement
+            WHERE metric = ?1 AND date BETWEEN ?2 AND ?3 ORDER BY date",
+        )
+        .expect("could not prepare statement");
+    let rows = stmt
+        .query_map([metric, from, to], |row| Ok((row.get(0)?, row.get(1)?)))
+        .expect("could not query");
+    rows.map(|row| row.expect("could not get row")).collect()
+}
+
+pub async fn get_metrics(Extension(db): Extension<Database>) -> impl IntoResponse {
+    tracing::info!("getting metrics");
+    let conn = db.connection().expect("could not get connection");
+    let mut qry = conn
+        .prepare_cached("SELECT name, unit FROM metric ORDER BY name")
+        .expect("could not prepare qry");
+    let metrics: Vec<Metric> = qry
+        .query_map([], |row| {
+            Ok(Metric {
+                name: row.get(0)?,
+                unit: row.get(1)?,
+            })
+        })
+        .expect("could not query")
+        .map(|row| row.expect("could not get row"))
+        .collect();
+    (StatusCode::OK, Json(metrics))
+}
+
+pub async fn set_metric(
+    Json(metric): Json<Metric>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("setting metric {:?}", metric);
+    if !valid_name(&metric.name) || metric.name == WEIGHT || metric.unit.trim().is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+    let conn = db.connection().expect("could not get connection");
+    conn.execute(
+        "INSERT INTO metric (name, unit) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET unit = ?2;",
+        params![metric.name, metric.unit.trim()],
+    )
+    .expect("could not insert metric into db");
+    StatusCode::CREATED
+}
+
+pub async fn add_measurement(
+    Json(m): Json<AddMeasurement>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("adding measurement {:?}", m);
+    if !check_date(&m.date) || !m.value.is_finite() {
+        return StatusCode::BAD_REQUEST;
+    }
+    let conn = db.connection().expect("could not get connection");
+    let value = if m.metric == WEIGHT {
+        Units::from_db(&conn).weight_in(m.value)
+    } else {
+        m.value
+    };
+    if !insert_measurement(&conn, &m.metric, &m.date, value, m.timestamp) {
+        return StatusCode::NOT_FOUND;
+    }
+    StatusCode::OK
+}
+
+pub async fn remove_measurement(
+    Path((metric, date)): Path<(String, String)>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("removing measurement {} {}", metric, date);
+    let conn = db.connection().expect("could not get connection");
+    let n_removed = conn
+        .execute(
+            "DELETE FROM measurement_reading WHERE metric = ?1 AND date = ?2;",
+            [&metric, &date],
+        )
+        .expect("could not remove measurement");
+    if n_removed == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+    StatusCode::OK
+}
+
+/// Every reading of the metric on that date, to find out which one is off
+pub async fn readings(
+    Path((metric, date)): Path<(String, String)>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting {} readings on {}", metric, date);
+    let conn = db.connection().expect("could not get connection");
+    let units = Units::from_db(&conn);
+    let mut qry = conn
+        .prepare_cached(
+            "SELECT id, timestamp, value FROM measurement_reading
+            WHERE metric = ?1 AND date = ?2 ORDER BY timestamp, id",
+        )
+        .expect("could not prepare qry");
+    let readings: Vec<Reading> = qry
+        .query_map([&metric, &date], |row| {
+            let value: f64 = row.get(2)?;
+            Ok(Reading {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                value: if metric == WEIGHT {
+                    units.weight(value)
+                } else {
+                    value
+                },
+            })
+        })
+        .expect("could not query")
+        .map(|row| row.expect("could not get row"))
+        .collect();
+    (StatusCode::OK, Json(readings))
+}
+
+pub async fn remove_reading(
+    Path(id): Path<u64>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("removing reading {}", id);
+    let conn = db.connection().expect("could not get connection");
+    let n_removed = conn
+        .execute("DELETE FROM measurement_reading WHERE id = ?1;", [id])
+        .expect("could not remove reading");
+    if n_removed == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+    StatusCode::OK
+}
+
+pub async fn measurement_history(
+    Path((metric, after_date)): Path<(String, String)>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting {} history after {}", metric, after_date);
+    let after_date = match parse_date(&after_date) {
+        Some(d) => to_year_month_day(&d),
+        None => return (StatusCode::BAD_REQUEST, Json(MeasurementHistory::default())),
+    };
+    let conn = db.connection().expect("could not get connection");
+    let unit = match metric_unit(&conn, &metric) {
+        Some(unit) => unit,
+        None => return (StatusCode::NOT_FOUND, Json(MeasurementHistory::default())),
+    };
+    let all_values = measurements_between(&conn, &metric, "0000-01-01", "9999-12-31");
+    let conf = Conf::from_db(&conn);
+    let (values, trend, weekly_rate) =
+        weight::history_after(all_values, conf.weight_smoothing, &after_date);
+    let history = MeasurementHistory {
+        unit,
+        values,
+        trend,
+        weekly_rate,
+    };
+    if metric != WEIGHT {
+        return (StatusCode::OK, Json(history));
+    }
+    let units = Units::of(&conf);
+    (
+        StatusCode::OK,
+        Json(MeasurementHistory {
+            unit: units.weight.as_str().to_string(),
+            values: units.weights(history.values),
+            trend: units.weights(history.trend),
+            weekly_rate: history.weekly_rate.map(|x| units.weight(x)),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_measurement() {
+        let conn = crate::migrate::test_db();
+        for (timestamp, value) in [(20, 80.0), (10, 81.0), (30, 79.0)] {
+            assert!(insert_measurement(
+                &conn,
+                WEIGHT,
+                "2022-05-01",
+                value,
+                Some(timestamp)
+            ));
+        }
+        assert!(!insert_measurement(
+            &conn,
+            "waist",
+            "2022-05-01",
+            90.0,
+            None
+        ));
+
+        for (mode, expected) in [
+            ("last", 79.0),
+            ("first", 81.0),
+            ("min", 79.0),
+            ("average", 80.0),
+        ] {
+            conn.execute(
+                "INSERT INTO conf (key, value) VALUES ('daily_measurement', ?1) ON CONFLICT DO UPDATE SET value = ?1;",
+                [mode],
+            )
+            .unwrap();
+            let weights = measurements_between(&conn, WEIGHT, "2022-01-01", "2022-12-31");
+            assert_eq!(
+                weights,
+                vec![("2022-05-01".to_string(), expected)],
+                "{}",
+                mode
+            );
+        }
+    }
+}
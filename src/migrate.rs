@@ -2,6 +2,7 @@ use crate::db::Database;
 use anyhow::{bail, Context, Result};
 use include_dir::Dir;
 use r2d2_sqlite::rusqlite::{Connection, ErrorCode};
+use std::path::Path;
 
 fn get_migrated(conn: &Connection) -> Result<Vec<String>> {
     let mut stmt = match conn.prepare("SELECT migration FROM sqlx_pg_migrate ORDER BY id;") {
@@ -21,18 +22,25 @@ fn get_migrated(conn: &Connection) -> Result<Vec<String>> {
         Err(e) => Err(e)?,
     };
     let migrated = stmt.query_map([], |row| row.get("migration"))?;
-    migrated
-        .map(|x| x.map_err(|e| anyhow::Error::from(e)))
-        .collect()
+    migrated.map(|x| x.map_err(anyhow::Error::from)).collect()
+}
+
+fn migration_number(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_str()?;
+    name.split('_').next()?.parse().ok()
 }
 
 /// Runs the migrations contained in the directory. See module documentation for
 /// more information.
 pub fn migrate(db: &Database, dir: &Dir<'_>) -> Result<()> {
+    let mut client = db.connection()?;
+    migrate_connection(&mut client, dir)
+}
+
+fn migrate_connection(client: &mut Connection, dir: &Dir<'_>) -> Result<()> {
     tracing::info!("running migrations");
 
-    let mut client = db.connection()?;
-    let migrated = get_migrated(&client).context("error getting migrations")?;
+    let migrated = get_migrated(client).context("error getting migrations")?;
     tracing::info!("got existing migrations from table");
 
     let tx = client.transaction().context("error creating transaction")?;
@@ -54,7 +62,8 @@ pub fn migrate(db: &Database, dir: &Dir<'_>) -> Result<()> {
     if migrated.len() > files.len() {
         bail!("some migrations were deleted")
     }
-    files.sort_by(|a, b| a.path().partial_cmp(b.path()).unwrap());
+    // by the number prefix first so that 10_x runs after 9_y
+    files.sort_by_key(|f| (migration_number(f.path()), f.path()));
     for (pos, f) in files.iter().enumerate() {
         let path = f.path().to_str().context("invalid path")?;
 
@@ -78,3 +87,11 @@ pub fn migrate(db: &Database, dir: &Dir<'_>) -> Result<()> {
     tracing::info!("sucessfully ran migrations");
     Ok(())
 }
+
+/// An in-memory database with every migration applied, for tests
+#[cfg(test)]
+pub fn test_db() -> Connection {
+    let mut conn = Connection::open_in_memory().expect("could not open db");
+    migrate_connection(&mut conn, &crate::MIGRATIONS).expect("could not run migrations");
+    conn
+}
@@ -1,27 +1,123 @@
+use crate::db::Database;
 use fuzzy_matcher::skim::SkimMatcherV2;
-use r2d2_sqlite::rusqlite::Connection;
+use r2d2_sqlite::rusqlite::{params, Connection};
 use serde::Serialize;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
 
+#[derive(Debug)]
 pub struct SearchItem {
     pub name: String,
     pub calories: f64,
 }
 
+#[derive(Serialize, Clone)]
+pub struct SearchVariant {
+    pub calories: f64,
+    pub count: u32,
+}
+
 #[derive(Serialize)]
 pub struct SearchResult {
     pub name: String,
+    /// Most frequently logged calorie value for this name, ties go to the newest
     pub calories: f64,
+    pub min_calories: f64,
+    pub max_calories: f64,
+    /// Number of items logged under this name
+    pub count: u32,
     pub positions: Vec<u32>,
+    /// Every distinct calorie value seen for this name, newest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variants: Option<Vec<SearchVariant>>,
 }
 
+/// All the items sharing the same normalized name
+struct NameGroup<'a> {
+    /// id of the newest item, used to break score ties
+    id: u64,
+    /// spelling of the newest item
+    name: &'a str,
+    variants: Vec<SearchVariant>,
+}
+
+impl NameGroup<'_> {
+    fn add(&mut self, calories: f64, count: u32) {
+        match self.variants.iter_mut().find(|v| v.calories == calories) {
+            Some(v) => v.count += count,
+            None => self.variants.push(SearchVariant { calories, count }),
+        }
+    }
+
+    fn into_result(self, positions: Vec<u32>, with_variants: bool) -> SearchResult {
+        // variants are ordered newest first so max_by_key would pick the oldest on ties
+        let mut best = &self.variants[0];
+        for v in &self.variants[1..] {
+            if v.count > best.count {
+                best = v;
+            }
+        }
+        let calories = best.calories;
+        let min_calories = self
+            .variants
+            .iter()
+            .map(|v| v.calories)
+            .fold(f64::INFINITY, f64::min);
+        let max_calories = self
+            .variants
+            .iter()
+            .map(|v| v.calories)
+            .fold(f64::NEG_INFINITY, f64::max);
+        SearchResult {
+            name: self.name.trim().to_string(),
+            calories,
+            min_calories,
+            max_calories,
+            count: self.variants.iter().map(|v| v.count).sum(),
+            positions,
+            variants: if with_variants {
+                Some(self.variants)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Lowercases and collapses whitespace so "Pizza " and "pizza" are the same food
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Change made to the items table, the FTS backend follows it with triggers
+#[derive(Debug)]
+pub enum Change {
+    Insert(u64, SearchItem),
+    /// New name and calories when they changed
+    Update(u64, Option<String>, Option<f64>),
+    Remove(u64),
+}
+
+/// Distinct (name, calories) rows read from items_fts by a search, the least
+/// recently logged ones past it are not ranked
+const MAX_FTS_ROWS: u32 = 1000;
+
 #[derive(Clone)]
 pub struct Searcher(Arc<SearcherInner>);
 
 struct SearcherInner {
     matcher: SkimMatcherV2,
-    items: RwLock<BTreeMap<u64, SearchItem>>,
+    backend: Backend,
+}
+
+enum Backend {
+    /// Every item is kept in memory and scanned on each search
+    Memory(RwLock<BTreeMap<u64, SearchItem>>),
+    /// Candidates come from the items_fts table, kept in sync with items by triggers
+    Fts(Database),
 }
 
 impl Searcher {
@@ -42,71 +138,312 @@ impl Searcher {
             );
         }
 
+        Self::with_backend(Backend::Memory(RwLock::new(items)))
+    }
+
+    /// Searches through the items_fts index instead of loading all the items in memory.
+    /// Only words starting with the query words are found, so this is less fuzzy than `new`.
+    pub fn new_fts(db: Database) -> Self {
+        Self::with_backend(Backend::Fts(db))
+    }
+
+    fn with_backend(backend: Backend) -> Self {
         Self(Arc::new(SearcherInner {
             matcher: SkimMatcherV2::default().ignore_case(),
-            items: RwLock::new(items),
+            backend,
         }))
     }
 
-    pub fn update(&self, id: u64, name: Option<String>, calories: Option<f64>) {
-        self.0
-            .items
-            .write()
-            .expect("could not lock write")
-            .get_mut(&id)
-            .map(move |x| {
-                if let Some(name) = name {
-                    x.name = name;
-                }
-                if let Some(calories) = calories {
-                    x.calories = calories;
-                }
-            });
+    /// The fts backend has nothing to update, triggers keep items_fts in sync
+    fn memory_items(&self) -> Option<&RwLock<BTreeMap<u64, SearchItem>>> {
+        match &self.0.backend {
+            Backend::Memory(items) => Some(items),
+            Backend::Fts(_) => None,
+        }
     }
 
-    pub fn insert(&self, id: u64, item: SearchItem) {
-        self.0
-            .items
-            .write()
-            .expect("could not lock write")
-            .insert(id, item);
+    /// Applies the changes of committed items under a single write lock
+    pub fn apply(&self, changes: Vec<Change>) {
+        let mut items = match self.memory_items() {
+            Some(items) => items.write().expect("could not lock write"),
+            None => return,
+        };
+        for change in changes {
+            match change {
+                Change::Insert(id, item) => {
+                    items.insert(id, item);
+                }
+                Change::Update(id, name, calories) => {
+                    if let Some(x) = items.get_mut(&id) {
+                        if let Some(name) = name {
+                            x.name = name;
+                        }
+                        if let Some(calories) = calories {
+                            x.calories = calories;
+                        }
+                    }
+                }
+                Change::Remove(id) => {
+                    items.remove(&id);
+                }
+            }
+        }
     }
 
-    pub fn remove(&self, id: u64) {
-        self.0
-            .items
-            .write()
-            .expect("could not lock write")
-            .remove(&id);
+    pub fn search(&self, qry: &str, with_variants: bool) -> Vec<SearchResult> {
+        match &self.0.backend {
+            Backend::Memory(items) => {
+                let items = items.read().expect("could not lock read");
+                let rows = items
+                    .iter()
+                    .rev()
+                    .map(|(&id, item)| (id, &*item.name, item.calories, 1));
+                self.rank(group_by_name(rows), qry, with_variants)
+            }
+            Backend::Fts(db) => {
+                let fts_qry = fts_query(qry);
+                if fts_qry.is_empty() {
+                    return vec![];
+                }
+                let conn = db.connection().expect("could not get connection");
+                // sqlite only lowercases ascii, the groups are merged by normalized name after
+                let mut stmt = conn
+                    .prepare_cached(
+                        "SELECT max(items.id) as id, items.name, items.calories, count(*) FROM items_fts
+                         JOIN items ON items.id = items_fts.rowid
+                         WHERE items_fts MATCH ?1
+                         GROUP BY items.name, items.calories ORDER BY id DESC LIMIT ?2",
+                    )
+                    .expect("could not prepare qry");
+                let found: Vec<(u64, String, f64, u32)> = stmt
+                    .query_map(params![fts_qry, MAX_FTS_ROWS], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                    })
+                    .expect("could not run qry")
+                    .collect::<Result<_, _>>()
+                    .expect("could not get row");
+                let rows = found
+                    .iter()
+                    .map(|(id, name, calories, count)| (*id, &**name, *calories, *count));
+                self.rank(group_by_name(rows), qry, with_variants)
+            }
+        }
     }
 
-    pub fn search(&self, qry: &str) -> Vec<SearchResult> {
-        let items = self.0.items.read().expect("could not lock read");
+    fn rank(
+        &self,
+        groups: HashMap<String, NameGroup>,
+        qry: &str,
+        with_variants: bool,
+    ) -> Vec<SearchResult> {
         let mut results = vec![];
-        let mut seen = HashSet::new();
-        for (&id, item) in items.iter().rev() {
-            if item.name.len() == 0 || !seen.insert(&*item.name) {
-                continue;
-            }
-            let res = self.0.matcher.fuzzy(&*item.name, qry, true);
+        for group in groups.into_values() {
+            let res = self.0.matcher.fuzzy(group.name, qry, true);
             if res.is_none() {
                 continue;
             }
             let (score, pos) = res.unwrap();
-            results.push((score, id, pos));
+            results.push((score, group, pos));
         }
-        results.sort_unstable_by_key(|(score, id, _)| (-*score, !*id));
+        results.sort_unstable_by_key(|(score, group, _)| (-*score, !group.id));
         results
             .into_iter()
             .take(5)
-            .map(|(_, id, pos)| {
-                let item = items.get(&id).unwrap();
-                SearchResult {
-                    name: item.name.clone(),
-                    calories: item.calories,
-                    positions: pos,
-                }
-            })
+            .map(|(_, group, pos)| group.into_result(pos, with_variants))
             .collect()
     }
 }
+
+/// Groups (id, name, calories, count) rows, which must be ordered newest first, by
+/// normalized name
+fn group_by_name<'a>(
+    rows: impl Iterator<Item = (u64, &'a str, f64, u32)>,
+) -> HashMap<String, NameGroup<'a>> {
+    let mut groups: HashMap<String, NameGroup> = HashMap::new();
+    for (id, name, calories, count) in rows {
+        let key = normalize_name(name);
+        if key.is_empty() {
+            continue;
+        }
+        groups
+            .entry(key)
+            .or_insert_with(|| NameGroup {
+                id,
+                name,
+                variants: vec![],
+            })
+            .add(calories, count);
+    }
+    groups
+}
+
+/// Turns "choc cake" into `"choc"* "cake"*` so every word is matched as a quoted prefix
+fn fts_query(qry: &str) -> String {
+    qry.split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    fn searcher_with(items: &[(&str, f64)]) -> Searcher {
+        let c = crate::migrate::test_db();
+        for (name, calories) in items {
+            c.execute(
+                "INSERT INTO items (name, calories, multiplier, date, timestamp) VALUES (?1, ?2, 1, '2022-01-01', 0)",
+                params![name, calories],
+            )
+            .unwrap();
+        }
+        Searcher::new(&c)
+    }
+
+    #[test]
+    fn test_most_common_calories() {
+        let s = searcher_with(&[
+            ("Pizza", 800.0),
+            ("pizza", 800.0),
+            ("pizza ", 80.0),
+            ("Apple", 50.0),
+        ]);
+        let res = s.search("pizz", true);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].name, "pizza");
+        assert_eq!(res[0].calories, 800.0);
+        assert_eq!(res[0].min_calories, 80.0);
+        assert_eq!(res[0].max_calories, 800.0);
+        assert_eq!(res[0].count, 3);
+        assert_eq!(res[0].variants.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_tie_goes_to_newest() {
+        let s = searcher_with(&[("rice", 200.0), ("rice", 250.0)]);
+        let res = s.search("rice", false);
+        assert_eq!(res[0].calories, 250.0);
+        assert!(res[0].variants.is_none());
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let s = searcher_with(&[("rice", 200.0), ("bread", 250.0)]);
+        s.apply(vec![
+            Change::Insert(
+                3,
+                SearchItem {
+                    name: "rice".to_string(),
+                    calories: 300.0,
+                },
+            ),
+            Change::Update(1, Some("pasta".to_string()), None),
+            Change::Remove(2),
+        ]);
+        assert_eq!(s.search("rice", false)[0].count, 1);
+        assert_eq!(s.search("pasta", false)[0].calories, 200.0);
+        assert!(s.search("bread", false).is_empty());
+    }
+
+    /// A migrated database in a temporary file, so the fts5 triggers exist.
+    /// The file is removed when dropped.
+    struct FtsDb {
+        db: Database,
+        path: PathBuf,
+    }
+
+    impl Drop for FtsDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn fts_db(name: &str) -> FtsDb {
+        let path =
+            std::env::temp_dir().join(format!("tinycalorie-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = Database::new(path.to_str().unwrap()).unwrap().0;
+        crate::migrate::migrate(&db, &crate::MIGRATIONS).unwrap();
+        FtsDb { db, path }
+    }
+
+    fn insert_items(db: &Database, items: impl Iterator<Item = (String, f64)>) {
+        let mut conn = db.connection().unwrap();
+        let tx = conn.transaction().unwrap();
+        for (name, calories) in items {
+            tx.execute(
+                "INSERT INTO items (name, calories, multiplier, date, timestamp) VALUES (?1, ?2, 1, '2022-01-01', 0)",
+                params![name, calories],
+            )
+            .unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn test_fts_follows_items() {
+        let tmp = fts_db("fts-sync");
+        let db = &tmp.db;
+        insert_items(
+            db,
+            [
+                ("Chocolate cake", 400.0),
+                ("Apple", 50.0),
+                ("apple", 60.0),
+                ("apple", 60.0),
+            ]
+            .into_iter()
+            .map(|(n, c)| (n.to_string(), c)),
+        );
+        let fts = Searcher::new_fts(db.clone());
+        let memory = Searcher::new(&db.connection().unwrap());
+
+        for qry in ["cake", "appl", "choc cake"] {
+            let a = fts.search(qry, false);
+            let b = memory.search(qry, false);
+            assert_eq!(a.len(), 1, "{}", qry);
+            assert_eq!(a[0].name, b[0].name);
+            assert_eq!(a[0].calories, b[0].calories);
+            assert_eq!(a[0].count, b[0].count);
+        }
+
+        let conn = db.connection().unwrap();
+        conn.execute("UPDATE items SET name = 'Pear' WHERE name = 'Apple'", [])
+            .unwrap();
+        assert_eq!(fts.search("appl", false)[0].count, 2);
+        assert_eq!(fts.search("pear", false)[0].count, 1);
+        conn.execute("DELETE FROM items WHERE name = 'Pear'", [])
+            .unwrap();
+        assert!(fts.search("pear", false).is_empty());
+        assert!(fts.search("\"", false).is_empty());
+    }
+
+    /// cargo test bench_backends -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_backends() {
+        let tmp = fts_db("bench");
+        let db = &tmp.db;
+        let foods = ["pizza", "apple", "banana", "chicken salad", "rice", "pasta"];
+        insert_items(
+            db,
+            (0..50_000).map(|i| (format!("{} {}", foods[i % foods.len()], i % 500), i as f64)),
+        );
+        let backends = [
+            ("memory", Searcher::new(&db.connection().unwrap())),
+            ("fts", Searcher::new_fts(db.clone())),
+        ];
+        for (name, searcher) in backends {
+            let start = Instant::now();
+            for qry in ["piz", "chick", "ban 42", "zzz"] {
+                for _ in 0..10 {
+                    searcher.search(qry, false);
+                }
+            }
+            println!("{}: {:?} per search", name, start.elapsed() / 40);
+        }
+    }
+}
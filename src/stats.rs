@@ -0,0 +1,736 @@
+use crate::budget::DAY_BUDGET_SUBQUERY;
+use crate::conf::{Conf, DAY_METABOLISM_SUBQUERY};
+use crate::db::Database;
+use crate::search::normalize_name;
+use crate::units::Units;
+use crate::{check_date, parse_date, to_year_month_day, today, weights_between};
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{Duration, NaiveDate};
+use r2d2_sqlite::rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Energy stored in a kg of body fat
+pub const KCAL_PER_KG: f64 = 7700.0;
+
+#[derive(Debug, Deserialize)]
+pub struct TdeeParams {
+    /// Last day of the window, YYYY-MM-DD. Defaults to today.
+    to: Option<String>,
+    /// Length of the window in days
+    days: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TdeeEstimate {
+    from: String,
+    to: String,
+    /// Estimated daily energy expenditure, missing when there is not enough data
+    tdee: Option<f64>,
+    /// Standard error of tdee coming from the weight regression
+    tdee_error: Option<f64>,
+    /// Average intake over the days that have items
+    avg_intake: Option<f64>,
+    /// Slope of the weight regression, in kg per week
+    weight_change_per_week: Option<f64>,
+    logged_days: u32,
+    weight_points: u32,
+    confidence: Confidence,
+    /// Whether the metabolism conf was updated with this estimate
+    applied: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Group {
+    Week,
+    Month,
+}
+
+impl Group {
+    /// SQL expression of the period containing the `date` column:
+    /// the YYYY-MM-DD monday of the week or the YYYY-MM month
+    fn sql_period(self) -> &'static str {
+        match self {
+            Group::Week => "date(date, '-6 days', 'weekday 1')",
+            Group::Month => "strftime('%Y-%m', date)",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsParams {
+    from: String,
+    to: String,
+    /// Defaults to week
+    group: Option<Group>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PeriodStats {
+    period: String,
+    logged_days: u32,
+    /// Average intake over the days that have items
+    avg_intake: Option<f64>,
+    days_over_budget: u32,
+    /// Sum of metabolism minus intake over the logged days
+    total_deficit: f64,
+    avg_weight: Option<f64>,
+    /// Last weight of the period minus the first one
+    weight_delta: Option<f64>,
+}
+
+/// Intake of a logged day along with the budget and metabolism in effect that day
+pub struct DayIntake {
+    period: String,
+    total: f64,
+    budget: f64,
+    metabolism: f64,
+}
+
+/// Aggregates the days and the (period, weight) points, both ordered by date, per period
+pub fn period_stats(days: &[DayIntake], weights: &[(String, f64)]) -> Vec<PeriodStats> {
+    let mut periods: BTreeMap<&str, (Vec<&DayIntake>, Vec<f64>)> = BTreeMap::new();
+    for day in days {
+        periods.entry(&day.period).or_default().0.push(day);
+    }
+    for (period, weight) in weights {
+        periods.entry(period).or_default().1.push(*weight);
+    }
+
+    periods
+        .into_iter()
+        .map(|(period, (days, weights))| {
+            let logged_days = days.len() as u32;
+            let intake: f64 = days.iter().map(|d| d.total).sum();
+            PeriodStats {
+                period: period.to_string(),
+                logged_days,
+                avg_intake: (logged_days > 0).then(|| intake / logged_days as f64),
+                days_over_budget: days.iter().filter(|d| d.total > d.budget).count() as u32,
+                total_deficit: days.iter().fold(0.0, |acc, d| acc + d.metabolism - d.total),
+                avg_weight: (!weights.is_empty())
+                    .then(|| weights.iter().sum::<f64>() / weights.len() as f64),
+                weight_delta: (weights.len() > 1).then(|| weights[weights.len() - 1] - weights[0]),
+            }
+        })
+        .collect()
+}
+
+impl PeriodStats {
+    fn in_units(self, units: Units) -> Self {
+        Self {
+            avg_intake: self.avg_intake.map(|x| units.energy(x)),
+            total_deficit: units.energy(self.total_deficit),
+            avg_weight: self.avg_weight.map(|x| units.weight(x)),
+            weight_delta: self.weight_delta.map(|x| units.weight(x)),
+            ..self
+        }
+    }
+}
+
+fn stats_between(conn: &Connection, from: &str, to: &str, group: Group) -> Vec<PeriodStats> {
+    let conf = Conf::from_db(conn);
+    let mut qry = conn
+        .prepare(&format!(
+            "SELECT {} as period, days.total, coalesce({}, ?3) as budget,
+                coalesce({}, ?4) as metabolism
+            FROM (
+                SELECT date, sum(calories * multiplier) as total FROM items
+                WHERE date BETWEEN ?1 AND ?2 GROUP BY date
+            ) days ORDER BY days.date",
+            group.sql_period(),
+            DAY_BUDGET_SUBQUERY,
+            DAY_METABOLISM_SUBQUERY
+        ))
+        .expect("could not prepare qry");
+    let days: Vec<DayIntake> = qry
+        .query_map(params![from, to, conf.budget, conf.metabolism], |row| {
+            Ok(DayIntake {
+                period: row.get("period")?,
+                total: row.get("total")?,
+                budget: row.get("budget")?,
+                metabolism: row.get("metabolism")?,
+            })
+        })
+        .expect("could not query")
+        .map(|row| row.expect("could not get row"))
+        .collect();
+
+    let mut qry = conn
+        .prepare(&format!(
+            "SELECT {} as period, weight FROM weight WHERE date BETWEEN ?1 AND ?2 ORDER BY date",
+            group.sql_period()
+        ))
+        .expect("could not prepare qry");
+    let weights: Vec<(String, f64)> = qry
+        .query_map([from, to], |row| Ok((row.get(0)?, row.get(1)?)))
+        .expect("could not query")
+        .map(|row| row.expect("could not get row"))
+        .collect();
+
+    period_stats(&days, &weights)
+}
+
+pub async fn stats(
+    Query(params): Query<StatsParams>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting stats {:?}", params);
+    if !check_date(&params.from) || !check_date(&params.to) || params.from > params.to {
+        return (StatusCode::BAD_REQUEST, Json(vec![]));
+    }
+    let conn = db.connection().expect("could not get connection");
+    let units = Units::from_db(&conn);
+    let stats = stats_between(
+        &conn,
+        &params.from,
+        &params.to,
+        params.group.unwrap_or(Group::Week),
+    )
+    .into_iter()
+    .map(|x| x.in_units(units))
+    .collect();
+    (StatusCode::OK, Json(stats))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopFoodsParams {
+    from: String,
+    to: String,
+    /// Length of each list, defaults to 10
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FoodStats {
+    /// Name of the most recent item among those with the same normalized name
+    name: String,
+    count: u32,
+    calories: f64,
+    /// Percent of the total intake of the period
+    share: f64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct TopFoods {
+    total: f64,
+    by_calories: Vec<FoodStats>,
+    by_count: Vec<FoodStats>,
+}
+
+/// Groups the (name, calories) items, ordered from oldest to newest, by normalized name
+pub fn top_foods(items: &[(String, f64)], limit: usize) -> TopFoods {
+    let total: f64 = items.iter().map(|x| x.1).sum();
+    let mut foods: BTreeMap<String, FoodStats> = BTreeMap::new();
+    for (name, calories) in items {
+        let food = foods
+            .entry(normalize_name(name))
+            .or_insert_with(|| FoodStats {
+                name: name.trim().to_string(),
+                count: 0,
+                calories: 0.0,
+                share: 0.0,
+            });
+        food.name = name.trim().to_string();
+        food.count += 1;
+        food.calories += calories;
+    }
+    let mut foods: Vec<FoodStats> = foods
+        .into_values()
+        .map(|food| FoodStats {
+            share: if total > 0.0 {
+                food.calories * 100.0 / total
+            } else {
+                0.0
+            },
+            ..food
+        })
+        .collect();
+
+    foods.sort_by(|a, b| {
+        b.calories
+            .partial_cmp(&a.calories)
+            .unwrap_or(Ordering::Equal)
+    });
+    let by_calories = foods.iter().take(limit).cloned().collect();
+    foods.sort_by(|a, b| {
+        b.count.cmp(&a.count).then(
+            b.calories
+                .partial_cmp(&a.calories)
+                .unwrap_or(Ordering::Equal),
+        )
+    });
+    foods.truncate(limit);
+    TopFoods {
+        total,
+        by_calories,
+        by_count: foods,
+    }
+}
+
+pub async fn get_top_foods(
+    Query(params): Query<TopFoodsParams>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting top foods {:?}", params);
+    if !check_date(&params.from) || !check_date(&params.to) || params.from > params.to {
+        return (StatusCode::BAD_REQUEST, Json(TopFoods::default()));
+    }
+    let conn = db.connection().expect("could not get connection");
+    let mut qry = conn
+        .prepare_cached(
+            "SELECT name, calories * multiplier FROM items
+            WHERE date BETWEEN ?1 AND ?2 ORDER BY timestamp",
+        )
+        .expect("could not prepare qry");
+    let items: Vec<(String, f64)> = qry
+        .query_map([&params.from, &params.to], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .expect("could not query")
+        .map(|row| row.expect("could not get row"))
+        .collect();
+
+    let units = Units::from_db(&conn);
+    let mut top = top_foods(&items, params.limit.unwrap_or(10));
+    top.total = units.energy(top.total);
+    for food in top.by_calories.iter_mut().chain(top.by_count.iter_mut()) {
+        food.calories = units.energy(food.calories);
+    }
+    (StatusCode::OK, Json(top))
+}
+
+/// Per day series of a year, only for the days with items or a weight
+#[derive(Serialize, Debug, Default)]
+pub struct Heatmap {
+    year: i32,
+    dates: Vec<String>,
+    /// Intake minus metabolism, negative when in deficit. Missing like total.
+    net: Vec<Option<f64>>,
+    /// Intake minus budget, negative when under budget. Missing like total.
+    budget_delta: Vec<Option<f64>>,
+    /// Missing on the days without items
+    total: Vec<Option<f64>>,
+    weight: Vec<Option<f64>>,
+}
+
+fn heatmap_of(conn: &Connection, year: i32) -> Heatmap {
+    let conf = Conf::from_db(conn);
+    let mut qry = conn
+        .prepare_cached(&format!(
+            "WITH days AS (
+                SELECT date FROM items WHERE date BETWEEN ?1 AND ?2
+                UNION SELECT date FROM weight WHERE date BETWEEN ?1 AND ?2
+            ), totals AS (
+                SELECT date, sum(calories * multiplier) as total FROM items
+                WHERE date BETWEEN ?1 AND ?2 GROUP BY date
+            )
+            SELECT days.date, totals.total, weight.weight,
+                coalesce({}, ?3) as budget,
+                coalesce({}, ?4) as metabolism
+            FROM days
+            LEFT JOIN totals ON totals.date = days.date
+            LEFT JOIN weight ON weight.date = days.date
+            ORDER BY days.date",
+            DAY_BUDGET_SUBQUERY, DAY_METABOLISM_SUBQUERY
+        ))
+        .expect("could not prepare qry");
+    let mut rows = qry
+        .query(params![
+            format!("{:04}-01-01", year),
+            format!("{:04}-12-31", year),
+            conf.budget,
+            conf.metabolism
+        ])
+        .expect("could not run qry");
+
+    let units = Units::of(&conf);
+    let mut heatmap = Heatmap {
+        year,
+        ..Default::default()
+    };
+    while let Ok(Some(row)) = rows.next() {
+        let total: Option<f64> = row.get_unwrap("total");
+        let budget: f64 = row.get_unwrap("budget");
+        let metabolism: f64 = row.get_unwrap("metabolism");
+        let weight: Option<f64> = row.get_unwrap("weight");
+        heatmap.dates.push(row.get_unwrap("date"));
+        heatmap
+            .net
+            .push(total.map(|x| units.energy(x - metabolism)));
+        heatmap
+            .budget_delta
+            .push(total.map(|x| units.energy(x - budget)));
+        heatmap.total.push(total.map(|x| units.energy(x)));
+        heatmap.weight.push(weight.map(|x| units.weight(x)));
+    }
+    heatmap
+}
+
+pub async fn heatmap(
+    Path(year): Path<i32>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting heatmap of {}", year);
+    if !(1000..=9999).contains(&year) {
+        return (StatusCode::BAD_REQUEST, Json(Heatmap::default()));
+    }
+    let conn = db.connection().expect("could not get connection");
+    (StatusCode::OK, Json(heatmap_of(&conn, year)))
+}
+
+/// Total intake of each day that has items, between the two YYYY-MM-DD dates included
+pub fn daily_totals(conn: &Connection, from: &str, to: &str) -> Vec<(String, f64)> {
+    let mut qry = conn
+        .prepare_cached(
+            "SELECT date, sum(calories * multiplier) as total FROM items
+            WHERE date BETWEEN ?1 AND ?2 GROUP BY date ORDER BY date",
+        )
+        .expect("could not prepare qry");
+    let rows = qry
+        .query_map([from, to], |row| Ok((row.get(0)?, row.get(1)?)))
+        .expect("could not query");
+    rows.map(|row| row.expect("could not get row")).collect()
+}
+
+/// Least squares fit of y = a + b * x, returns the slope b and its standard error
+fn linear_regression(points: &[(f64, f64)]) -> Option<(f64, Option<f64>)> {
+    let n = points.len() as f64;
+    if points.len() < 2 {
+        return None;
+    }
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let slope = sxy / sxx;
+    if points.len() < 3 {
+        return Some((slope, None));
+    }
+    let intercept = mean_y - slope * mean_x;
+    let residuals: f64 = points
+        .iter()
+        .map(|p| (p.1 - intercept - slope * p.0).powi(2))
+        .sum();
+    Some((slope, Some((residuals / (n - 2.0) / sxx).sqrt())))
+}
+
+/// Energy balance: whatever was eaten and did not end up as weight change was spent.
+/// `intake` and `weights` are (date, value) pairs inside the window starting at `from`.
+pub fn estimate_tdee(
+    from: NaiveDate,
+    to: NaiveDate,
+    intake: &[(String, f64)],
+    weights: &[(String, f64)],
+) -> TdeeEstimate {
+    let logged_days = intake.len() as u32;
+    let avg_intake =
+        (logged_days > 0).then(|| intake.iter().map(|x| x.1).sum::<f64>() / logged_days as f64);
+
+    let points: Vec<(f64, f64)> = weights
+        .iter()
+        .filter_map(|(date, w)| Some(((parse_date(date)? - from).num_days() as f64, *w)))
+        .collect();
+    let regression = linear_regression(&points);
+
+    let tdee = avg_intake
+        .zip(regression)
+        .map(|(intake, (slope, _))| intake - slope * KCAL_PER_KG);
+    let tdee_error = regression
+        .and_then(|(_, err)| err)
+        .map(|err| err * KCAL_PER_KG);
+
+    let weight_points = points.len() as u32;
+    let confidence = match (tdee, tdee_error) {
+        (Some(_), Some(err)) if logged_days >= 21 && weight_points >= 10 && err < 150.0 => {
+            Confidence::High
+        }
+        (Some(_), Some(err)) if logged_days >= 10 && weight_points >= 4 && err < 400.0 => {
+            Confidence::Medium
+        }
+        _ => Confidence::Low,
+    };
+
+    TdeeEstimate {
+        from: to_year_month_day(&from),
+        to: to_year_month_day(&to),
+        tdee,
+        tdee_error,
+        avg_intake,
+        weight_change_per_week: regression.map(|(slope, _)| slope * 7.0),
+        logged_days,
+        weight_points,
+        confidence,
+        applied: false,
+    }
+}
+
+impl TdeeEstimate {
+    fn in_units(self, units: Units) -> Self {
+        Self {
+            tdee: self.tdee.map(|x| units.energy(x)),
+            tdee_error: self.tdee_error.map(|x| units.energy(x)),
+            avg_intake: self.avg_intake.map(|x| units.energy(x)),
+            weight_change_per_week: self.weight_change_per_week.map(|x| units.weight(x)),
+            ..self
+        }
+    }
+}
+
+fn tdee_for(conn: &Connection, params: &TdeeParams) -> Option<TdeeEstimate> {
+    let to = match &params.to {
+        Some(to) if check_date(to) => parse_date(to)?,
+        Some(_) => return None,
+        None => today(conn),
+    };
+    let days = params.days.unwrap_or(28);
+    if !(2..=3650).contains(&days) {
+        return None;
+    }
+    let from = to - Duration::days(days as i64 - 1);
+    let (from_s, to_s) = (to_year_month_day(&from), to_year_month_day(&to));
+
+    let intake = daily_totals(conn, &from_s, &to_s);
+    let weights = weights_between(conn, &from_s, &to_s);
+    Some(estimate_tdee(from, to, &intake, &weights))
+}
+
+pub async fn tdee(
+    Query(params): Query<TdeeParams>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("estimating tdee {:?}", params);
+    let conn = db.connection().expect("could not get connection");
+    match tdee_for(&conn, &params) {
+        Some(estimate) => (
+            StatusCode::OK,
+            Json(Some(estimate.in_units(Units::from_db(&conn)))),
+        ),
+        None => (StatusCode::BAD_REQUEST, Json(None)),
+    }
+}
+
+/// Same as `tdee`, then stores the estimate as the metabolism from today
+/// unless the confidence is low.
+pub async fn apply_tdee(
+    Query(params): Query<TdeeParams>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("applying tdee estimate {:?}", params);
+    let conn = db.connection().expect("could not get connection");
+    let mut estimate = match tdee_for(&conn, &params) {
+        Some(estimate) => estimate,
+        None => return (StatusCode::BAD_REQUEST, Json(None)),
+    };
+    let confident = estimate.confidence != Confidence::Low;
+    // a metabolism rounded to 0 would not be valid
+    if let Some(tdee) = estimate
+        .tdee
+        .map(f64::round)
+        .filter(|&x| confident && x >= 1.0)
+    {
+        Conf::store(
+            &conn,
+            "metabolism",
+            &tdee.to_string(),
+            None,
+            &to_year_month_day(&today(&conn)),
+        )
+        .expect("estimated metabolism should be valid");
+        estimate.applied = true;
+    }
+    (
+        StatusCode::OK,
+        Json(Some(estimate.in_units(Units::from_db(&conn)))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::budget::daily_budget;
+
+    #[test]
+    fn test_estimate_tdee() {
+        let from = NaiveDate::from_ymd(2022, 1, 1);
+        let to = from + Duration::days(27);
+        let mut intake = vec![];
+        let mut weights = vec![];
+        for i in 0..28 {
+            let date = to_year_month_day(&(from + Duration::days(i)));
+            intake.push((date.clone(), 1800.0));
+            // losing 0.5kg per week, with a bit of water noise
+            let noise = if i % 2 == 0 { 0.2 } else { -0.2 };
+            weights.push((date, 80.0 - 0.5 / 7.0 * i as f64 + noise));
+        }
+        let estimate = estimate_tdee(from, to, &intake, &weights);
+        let tdee = estimate.tdee.unwrap();
+        assert!(
+            (tdee - (1800.0 + 0.5 / 7.0 * KCAL_PER_KG)).abs() < 50.0,
+            "{}",
+            tdee
+        );
+        assert_eq!(estimate.confidence, Confidence::High);
+
+        let estimate = estimate_tdee(from, to, &intake[..3], &weights[..1]);
+        assert_eq!(estimate.tdee, None);
+        assert_eq!(estimate.confidence, Confidence::Low);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let conn = crate::migrate::test_db();
+        Conf::store(
+            &conn,
+            "metabolism",
+            "2000",
+            Some("2022-03-01"),
+            "2022-01-01",
+        )
+        .unwrap();
+        // a sunday
+        conn.execute(
+            "INSERT INTO budget_schedule VALUES ('2022-01-01', 6, 2500);",
+            [],
+        )
+        .unwrap();
+        Conf::store(&conn, "budget", "1800", Some("2022-03-10"), "2022-01-01").unwrap();
+        for (date, calories) in [
+            ("2022-02-06", 2000.0),
+            ("2022-03-07", 1000.0),
+            ("2022-03-13", 1900.0),
+        ] {
+            conn.execute(
+                "INSERT INTO items (name, calories, multiplier, date, timestamp) VALUES ('x', ?1, 1, ?2, 0);",
+                params![calories, date],
+            )
+            .unwrap();
+        }
+        crate::metrics::insert_measurement(&conn, "weight", "2022-03-08", 80.0, None);
+
+        let h = heatmap_of(&conn, 2022);
+        assert_eq!(
+            h.dates,
+            vec!["2022-02-06", "2022-03-07", "2022-03-08", "2022-03-13"]
+        );
+        assert_eq!(
+            h.net,
+            vec![
+                Some(2000.0 - 2200.0),
+                Some(1000.0 - 2000.0),
+                None,
+                Some(1900.0 - 2000.0)
+            ]
+        );
+        // the conf budget of 2022-03-10 is newer than the sunday schedule
+        assert_eq!(
+            h.budget_delta,
+            vec![
+                Some(2000.0 - 2500.0),
+                Some(1000.0 - 1500.0),
+                None,
+                Some(1900.0 - 1800.0)
+            ]
+        );
+        assert_eq!(
+            h.total,
+            vec![Some(2000.0), Some(1000.0), None, Some(1900.0)]
+        );
+        assert_eq!(h.weight, vec![None, None, Some(80.0), None]);
+        for ((date, delta), total) in h.dates.iter().zip(&h.budget_delta).zip(&h.total) {
+            assert_eq!(*delta, total.map(|x| x - daily_budget(&conn, date)));
+        }
+    }
+
+    #[test]
+    fn test_top_foods() {
+        let items: Vec<(String, f64)> = [
+            ("Apple", 80.0),
+            ("pizza", 800.0),
+            ("apple ", 90.0),
+            ("Apple", 70.0),
+            ("Rice", 260.0),
+        ]
+        .iter()
+        .map(|(n, c)| (n.to_string(), *c))
+        .collect();
+        let top = top_foods(&items, 2);
+        assert_eq!(top.total, 1300.0);
+        assert_eq!(top.by_calories[0].name, "pizza");
+        assert_eq!(top.by_calories[1].name, "Rice");
+        assert_eq!(
+            top.by_count[0],
+            FoodStats {
+                name: "Apple".to_string(),
+                count: 3,
+                calories: 240.0,
+                share: 240.0 * 100.0 / 1300.0,
+            }
+        );
+        assert_eq!(top.by_count.len(), 2);
+    }
+
+    #[test]
+    fn test_period_stats() {
+        let day = |period: &str, total| DayIntake {
+            period: period.to_string(),
+            total,
+            budget: 1500.0,
+            metabolism: 2000.0,
+        };
+        let days = [
+            day("2022-01", 1400.0),
+            day("2022-01", 1800.0),
+            day("2022-02", 1000.0),
+        ];
+        let weights = [
+            ("2022-01".to_string(), 80.0),
+            ("2022-01".to_string(), 79.0),
+            ("2022-01".to_string(), 79.5),
+            ("2022-03".to_string(), 78.0),
+        ];
+        let stats = period_stats(&days, &weights);
+        assert_eq!(
+            stats,
+            vec![
+                PeriodStats {
+                    period: "2022-01".to_string(),
+                    logged_days: 2,
+                    avg_intake: Some(1600.0),
+                    days_over_budget: 1,
+                    total_deficit: 800.0,
+                    avg_weight: Some(79.5),
+                    weight_delta: Some(-0.5),
+                },
+                PeriodStats {
+                    period: "2022-02".to_string(),
+                    logged_days: 1,
+                    avg_intake: Some(1000.0),
+                    total_deficit: 1000.0,
+                    ..Default::default()
+                },
+                PeriodStats {
+                    period: "2022-03".to_string(),
+                    avg_weight: Some(78.0),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::budget::logged_days;
+use crate::db::Database;
+use crate::{check_date, parse_date, to_year_month_day, today};
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{Duration, NaiveDate};
+use r2d2_sqlite::rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct StreakParams {
+    /// Defaults to 30 days before `to`
+    from: Option<String>,
+    /// Defaults to today
+    to: Option<String>,
+}
+
+/// Length of the streaks ending on a logged day
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DayStreak {
+    /// Consecutive days with items
+    pub logging: u32,
+    /// Consecutive logged days at or under budget, 0 when over budget
+    pub under_budget: u32,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct StreakReport {
+    from: String,
+    to: String,
+    days: u32,
+    logged_days: u32,
+    under_budget_days: u32,
+    /// Percent of the days of the period that have items
+    logging_percent: f64,
+    /// Percent of the logged days that are at or under budget
+    adherence_percent: f64,
+    /// Streaks ending on `to`, or on the day before when `to` is not logged yet
+    current: DayStreak,
+    /// Longest streaks ending in the period
+    longest: DayStreak,
+}
+
+/// Streaks ending on each of the (date, under budget) logged days, ordered by date
+pub fn running_streaks(days: &[(NaiveDate, bool)]) -> Vec<DayStreak> {
+    let mut res: Vec<DayStreak> = Vec::with_capacity(days.len());
+    let mut prev: Option<(NaiveDate, DayStreak)> = None;
+    for &(date, under_budget) in days {
+        let streak = match prev {
+            Some((prev_date, prev_streak)) if date - prev_date == Duration::days(1) => DayStreak {
+                logging: prev_streak.logging + 1,
+                under_budget: if under_budget {
+                    prev_streak.under_budget + 1
+                } else {
+                    0
+                },
+            },
+            _ => DayStreak {
+                logging: 1,
+                under_budget: under_budget as u32,
+            },
+        };
+        prev = Some((date, streak));
+        res.push(streak);
+    }
+    res
+}
+
+/// Logged days up to `to` included along with their streaks. The whole history is
+/// needed so that streaks starting before the period are counted fully.
+pub fn streaks_until(conn: &Connection, to: &str) -> Vec<(NaiveDate, bool, DayStreak)> {
+    let days: Vec<(NaiveDate, bool)> = logged_days(conn, "0000-01-01", to)
+        .into_iter()
+        .filter_map(|d| Some((parse_date(&d.date)?, d.total <= d.budget)))
+        .collect();
+    let streaks = running_streaks(&days);
+    days.into_iter()
+        .zip(streaks)
+        .map(|((date, under_budget), streak)| (date, under_budget, streak))
+        .collect()
+}
+
+pub fn report(
+    from: NaiveDate,
+    to: NaiveDate,
+    days: &[(NaiveDate, bool, DayStreak)],
+) -> StreakReport {
+    let in_period: Vec<_> = days.iter().filter(|d| d.0 >= from && d.0 <= to).collect();
+    let n_days = (to - from).num_days() as u32 + 1;
+    let logged_days = in_period.len() as u32;
+    let under_budget_days = in_period.iter().filter(|d| d.1).count() as u32;
+    let current = days
+        .last()
+        .filter(|d| to - d.0 <= Duration::days(1))
+        .map(|d| d.2)
+        .unwrap_or_default();
+    let longest = DayStreak {
+        logging: in_period.iter().map(|d| d.2.logging).max().unwrap_or(0),
+        under_budget: in_period
+            .iter()
+            .map(|d| d.2.under_budget)
+            .max()
+            .unwrap_or(0),
+    };
+    StreakReport {
+        from: to_year_month_day(&from),
+        to: to_year_month_day(&to),
+        days: n_days,
+        logged_days,
+        under_budget_days,
+        logging_percent: logged_days as f64 * 100.0 / n_days as f64,
+        adherence_percent: if logged_days > 0 {
+            under_budget_days as f64 * 100.0 / logged_days as f64
+        } else {
+            0.0
+        },
+        current,
+        longest,
+    }
+}
+
+fn parse_param(date: &Option<String>, default: NaiveDate) -> Option<NaiveDate> {
+    match date {
+        Some(d) if check_date(d) => parse_date(d),
+        Some(_) => None,
+        None => Some(default),
+    }
+}
+
+pub async fn streaks(
+    Query(params): Query<StreakParams>,
+    Extension(db): Extension<Database>,
+) -> impl IntoResponse {
+    tracing::info!("getting streaks {:?}", params);
+    let conn = db.connection().expect("could not get connection");
+    let to = parse_param(&params.to, today(&conn));
+    let from = to.and_then(|to| parse_param(&params.from, to - Duration::days(29)));
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) if from <= to => (from, to),
+        _ => return (StatusCode::BAD_REQUEST, Json(None)),
+    };
+    let days = streaks_until(&conn, &to_year_month_day(&to));
+    (StatusCode::OK, Json(Some(report(from, to, &days))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streaks() {
+        let d = |day| NaiveDate::from_ymd(2022, 5, day);
+        let days = [
+            (d(1), true),
+            (d(2), true),
+            (d(3), false),
+            (d(4), true),
+            (d(6), true),
+            (d(7), true),
+        ];
+        let streaks = running_streaks(&days);
+        let logging: Vec<u32> = streaks.iter().map(|s| s.logging).collect();
+        let under_budget: Vec<u32> = streaks.iter().map(|s| s.under_budget).collect();
+        assert_eq!(logging, vec![1, 2, 3, 4, 1, 2]);
+        assert_eq!(under_budget, vec![1, 2, 0, 1, 1, 2]);
+
+        let days: Vec<_> = days
+            .iter()
+            .zip(streaks)
+            .map(|(&(date, under), s)| (date, under, s))
+            .collect();
+        let r = report(d(2), d(8), &days);
+        assert_eq!(r.days, 7);
+        assert_eq!(r.logged_days, 5);
+        assert_eq!(r.under_budget_days, 4);
+        assert_eq!(r.adherence_percent, 80.0);
+        assert_eq!(
+            r.current,
+            DayStreak {
+                logging: 2,
+                under_budget: 2
+            }
+        );
+        assert_eq!(
+            r.longest,
+            DayStreak {
+                logging: 4,
+                under_budget: 2
+            }
+        );
+        assert_eq!(report(d(2), d(9), &days).current, DayStreak::default());
+    }
+}
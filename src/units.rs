@@ -0,0 +1,158 @@
+use crate::conf::{Conf, ConfHistoryEntry, EnergyUnit, WeightUnit};
+use crate::stats::KCAL_PER_KG;
+use r2d2_sqlite::rusqlite::Connection;
+
+const KJ_PER_KCAL: f64 = 4.184;
+const LB_PER_KG: f64 = 2.204_622_621_85;
+/// Decimals kept by the canonical conf amounts and by the conf amounts sent back
+const CANONICAL_DECIMALS: i32 = 6;
+const CONF_DECIMALS: i32 = 2;
+
+fn round_to(v: f64, decimals: i32) -> f64 {
+    let f = 10f64.powi(decimals);
+    (v * f).round() / f
+}
+
+/// Converts between the canonical units stored in the db (kg and kcal)
+/// and the units chosen in the conf, used in requests and responses.
+#[derive(Clone, Copy, Debug)]
+pub struct Units {
+    pub weight: WeightUnit,
+    pub energy: EnergyUnit,
+}
+
+impl Units {
+    pub fn of(conf: &Conf) -> Self {
+        Self {
+            weight: conf.weight_unit,
+            energy: conf.energy_unit,
+        }
+    }
+
+    pub fn from_db(conn: &Connection) -> Self {
+        Self::of(&Conf::from_db(conn))
+    }
+
+    pub fn weight(&self, kg: f64) -> f64 {
+        match self.weight {
+            WeightUnit::Kg => kg,
+            WeightUnit::Lb => kg * LB_PER_KG,
+        }
+    }
+
+    pub fn energy(&self, kcal: f64) -> f64 {
+        match self.energy {
+            EnergyUnit::Kcal => kcal,
+            EnergyUnit::Kj => kcal * KJ_PER_KCAL,
+        }
+    }
+
+    /// Back to kg
+    pub fn weight_in(&self, v: f64) -> f64 {
+        v / self.weight(1.0)
+    }
+
+    /// Back to kcal
+    pub fn energy_in(&self, v: f64) -> f64 {
+        v / self.energy(1.0)
+    }
+
+    pub fn weights(&self, series: Vec<(String, f64)>) -> Vec<(String, f64)> {
+        series
+            .into_iter()
+            .map(|(d, kg)| (d, self.weight(kg)))
+            .collect()
+    }
+
+    /// Converts the amount of a budget, metabolism or goal_weight conf value back to
+    /// canonical units. Other keys and unparsable values are left to the conf validation.
+    pub fn conf_value_in(&self, key: &str, value: &str) -> String {
+        let v: f64 = match value.trim().parse() {
+            Ok(v) => v,
+            Err(_) => return value.to_string(),
+        };
+        match key {
+            "budget" | "metabolism" => round_to(self.energy_in(v), CANONICAL_DECIMALS).to_string(),
+            "goal_weight" => round_to(self.weight_in(v), CANONICAL_DECIMALS).to_string(),
+            _ => value.to_string(),
+        }
+    }
+
+    /// Inverse of conf_value_in, for the values stored in conf_history
+    pub fn conf_value(&self, key: &str, value: &str) -> String {
+        let v: f64 = match value.trim().parse() {
+            Ok(v) => v,
+            Err(_) => return value.to_string(),
+        };
+        match key {
+            "budget" | "metabolism" => round_to(self.energy(v), CONF_DECIMALS).to_string(),
+            "goal_weight" => round_to(self.weight(v), CONF_DECIMALS).to_string(),
+            _ => value.to_string(),
+        }
+    }
+
+    /// Energy stored in one weight unit of body fat
+    pub fn energy_per_weight(&self) -> f64 {
+        self.energy(KCAL_PER_KG) / self.weight(1.0)
+    }
+
+    /// The conf with its amounts in these units
+    pub fn conf(&self, conf: Conf) -> Conf {
+        Conf {
+            budget: round_to(self.energy(conf.budget), CONF_DECIMALS),
+            metabolism: round_to(self.energy(conf.metabolism), CONF_DECIMALS),
+            goal_weight: conf
+                .goal_weight
+                .map(|w| round_to(self.weight(w), CONF_DECIMALS)),
+            ..conf
+        }
+    }
+
+    /// The conf history with its amounts in these units
+    pub fn conf_history(&self, history: Vec<ConfHistoryEntry>) -> Vec<ConfHistoryEntry> {
+        history
+            .into_iter()
+            .map(|e| ConfHistoryEntry {
+                value: self.conf_value(&e.key, &e.value),
+                ..e
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let units = Units {
+            weight: WeightUnit::Lb,
+            energy: EnergyUnit::Kj,
+        };
+        assert!((units.weight(1.0) - 2.2046).abs() < 1e-3);
+        assert!((units.energy(100.0) - 418.4).abs() < 1e-9);
+        assert!((units.weight_in(units.weight(80.0)) - 80.0).abs() < 1e-9);
+        assert!((units.energy_in(units.energy(1500.0)) - 1500.0).abs() < 1e-9);
+        assert!((units.energy_per_weight() - 7700.0 * 4.184 / 2.2046).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_conf_round_trip() {
+        let units = Units {
+            weight: WeightUnit::Lb,
+            energy: EnergyUnit::Kj,
+        };
+        let mut conf = Conf::default();
+        let goal_weight = units.conf_value_in("goal_weight", "160");
+        conf.set("goal_weight", &goal_weight).unwrap();
+        let budget = units.conf_value_in("budget", "8000");
+        conf.set("budget", &budget).unwrap();
+        assert_eq!(budget, "1912.045889");
+        let conf = units.conf(conf);
+        assert_eq!(conf.goal_weight, Some(160.0));
+        assert_eq!(conf.budget, 8000.0);
+        assert_eq!(units.conf_value("budget", &budget), "8000");
+        assert_eq!(units.conf_value("weight_unit", "lb"), "lb");
+    }
+}
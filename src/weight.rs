@@ -0,0 +1,96 @@
+use crate::parse_date;
+use chrono::NaiveDate;
+
+/// (YYYY-MM-DD date, value) points ordered by date
+pub type Series = Vec<(String, f64)>;
+
+/// Exponential moving average of the (date, weight) points, ordered by date, as in The Hacker's Diet.
+/// `smoothing` is the weight given to a new point one day after the previous one,
+/// longer gaps give more weight to the new point as if the missing days were interpolated.
+pub fn trend(weights: &[(String, f64)], smoothing: f64) -> Vec<(String, f64)> {
+    let mut res: Vec<(String, f64)> = Vec::with_capacity(weights.len());
+    let mut prev: Option<(NaiveDate, f64)> = None;
+    for (date, weight) in weights {
+        let day = parse_date(date);
+        let value = match (prev, day) {
+            (Some((prev_day, prev_trend)), Some(day)) => {
+                let gap = (day - prev_day).num_days().max(1) as i32;
+                let alpha = 1.0 - (1.0 - smoothing).powi(gap);
+                prev_trend + alpha * (weight - prev_trend)
+            }
+            _ => *weight,
+        };
+        if let Some(day) = day {
+            prev = Some((day, value));
+        }
+        res.push((date.clone(), value));
+    }
+    res
+}
+
+/// Values and trend from `after_date` (YYYY-MM-DD) along with the weekly rate.
+/// The trend is computed over all the values so it does not restart at after_date.
+pub fn history_after(
+    all_values: Series,
+    smoothing: f64,
+    after_date: &str,
+) -> (Series, Series, Option<f64>) {
+    let all_trend = trend(&all_values, smoothing);
+    let rate = weekly_rate(&all_trend);
+    let keep = |v: Series| v.into_iter().filter(|x| &*x.0 >= after_date).collect();
+    (keep(all_values), keep(all_trend), rate)
+}
+
+/// Change of the trend over the last week of data, in weight per week
+pub fn weekly_rate(trend: &[(String, f64)]) -> Option<f64> {
+    let (last_date, last) = trend.last()?;
+    let last_day = parse_date(last_date)?;
+    // newest point at least a week older than the last one, or the oldest point
+    let (prev_day, prev) = trend
+        .iter()
+        .rev()
+        .filter_map(|(date, v)| Some((parse_date(date)?, *v)))
+        .find(|(day, _)| (last_day - *day).num_days() >= 7)
+        .or_else(|| Some((parse_date(&trend.first()?.0)?, trend.first()?.1)))?;
+    let days = (last_day - prev_day).num_days();
+    if days == 0 {
+        return None;
+    }
+    Some((last - prev) / days as f64 * 7.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(v: &[(&str, f64)]) -> Vec<(String, f64)> {
+        v.iter().map(|(d, w)| (d.to_string(), *w)).collect()
+    }
+
+    #[test]
+    fn test_trend() {
+        let weights = points(&[
+            ("2022-01-01", 80.0),
+            ("2022-01-02", 81.0),
+            ("2022-01-04", 80.0),
+        ]);
+        let t = trend(&weights, 0.1);
+        assert_eq!(t[0].1, 80.0);
+        assert!((t[1].1 - 80.1).abs() < 1e-9);
+        // two days gap: alpha = 1 - 0.9^2 = 0.19
+        assert!((t[2].1 - (80.1 + 0.19 * (80.0 - 80.1))).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_weekly_rate() {
+        let t = points(&[
+            ("2022-01-01", 81.0),
+            ("2022-01-08", 80.5),
+            ("2022-01-15", 80.0),
+        ]);
+        assert_eq!(weekly_rate(&t), Some(-0.5));
+        assert_eq!(weekly_rate(&t[..1]), None);
+        let t = points(&[("2022-01-01", 81.0), ("2022-01-04", 80.7)]);
+        assert!((weekly_rate(&t).unwrap() + 0.7).abs() < 1e-9);
+    }
+}